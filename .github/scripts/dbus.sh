@@ -0,0 +1,51 @@
+#!/bin/sh
+# Checks the D-Bus interface of a window on a private session bus:
+#   dbus-run-session -- sh .github/scripts/dbus.sh target/debug/ripdrag
+# ripdrag needs a display, in CI it runs under xvfb-run.
+set -eu
+
+ripdrag=${1:-target/debug/ripdrag}
+dir=$(mktemp -d)
+touch "$dir/a" "$dir/b"
+
+"$ripdrag" --dbus --unique "$dir/a" &
+pid=$!
+trap 'kill $pid 2>/dev/null || true; rm -rf "$dir"' EXIT
+
+call() {
+    gdbus call --session --dest it.catboy.ripdrag --object-path /it/catboy/ripdrag \
+        --method "it.catboy.ripdrag.Window.$@"
+}
+
+fail() {
+    echo "$1" >&2
+    exit 1
+}
+
+# wait for the window to own its name
+for _ in $(seq 100); do
+    call ListFiles >/dev/null 2>&1 && break
+    sleep 0.1
+done
+
+call ListFiles | grep -q "file://$dir/a'" || fail "ListFiles is missing the initial file"
+
+call AddFiles "['$dir/b']" >/dev/null
+call ListFiles | grep -q "file://$dir/b'" || fail "AddFiles didn't add the file"
+
+if call AddFiles "['$dir/missing']" >/dev/null 2>&1; then
+    fail "AddFiles accepted a missing file"
+fi
+
+call RemoveFiles "['$dir/a']" >/dev/null
+if call ListFiles | grep -q "file://$dir/a'"; then
+    fail "RemoveFiles didn't remove the file"
+fi
+
+call Clear >/dev/null
+[ "$(call ListFiles)" = "(@as [],)" ] || fail "Clear didn't empty the list"
+
+call Quit >/dev/null
+wait $pid
+trap 'rm -rf "$dir"' EXIT
+echo "D-Bus interface OK"
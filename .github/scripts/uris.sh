@@ -0,0 +1,73 @@
+#!/bin/sh
+# Checks that file:// and http:// URIs are added, as arguments and on stdin, by asking the
+# windows for their list over --socket:
+#   dbus-run-session -- sh .github/scripts/uris.sh target/debug/ripdrag
+# http:// goes through GVfs, which is started on the session bus. ripdrag needs a display,
+# in CI it runs under xvfb-run.
+set -eu
+
+ripdrag=${1:-target/debug/ripdrag}
+port=${PORT:-8731}
+dir=$(mktemp -d)
+mkdir -m 700 "$dir/run"
+mkdir "$dir/www"
+export XDG_RUNTIME_DIR="$dir/run"
+touch "$dir/a" "$dir/b"
+echo c >"$dir/www/c.txt"
+echo d >"$dir/www/d.txt"
+
+python3 -m http.server --bind 127.0.0.1 --directory "$dir/www" "$port" >/dev/null 2>&1 &
+pids=$!
+trap 'kill $pids 2>/dev/null || true; rm -rf "$dir"' EXIT
+
+fail() {
+    echo "$1" >&2
+    exit 1
+}
+
+# sends a command to the window listening on NAME and prints its reply
+send() {
+    python3 - "$XDG_RUNTIME_DIR/ripdrag/$1.sock" "$2" <<'EOF'
+import socket
+import sys
+
+client = socket.socket(socket.AF_UNIX)
+client.connect(sys.argv[1])
+client.sendall(sys.argv[2].encode() + b"\n")
+client.shutdown(socket.SHUT_WR)
+sys.stdout.write(client.makefile().read())
+EOF
+}
+
+# whether the window listening on NAME lists all the other arguments
+lists() {
+    list=$(send "$1" list) || return 1
+    shift
+    for entry in "$@"; do
+        printf '%s\n' "$list" | grep -qxF "$entry" || return 1
+    done
+}
+
+# retries the command for up to ten seconds
+retry() {
+    for _ in $(seq 100); do
+        "$@" >/dev/null 2>&1 && return
+        sleep 0.1
+    done
+    fail "Timed out: $*"
+}
+
+retry python3 -c "import urllib.request; urllib.request.urlopen('http://localhost:$port/c.txt')"
+
+"$ripdrag" --socket args "file://$dir/a" "http://localhost:$port/c.txt" &
+pids="$pids $!"
+retry lists args "$dir/a" "http://localhost:$port/c.txt"
+
+printf 'file://%s\nhttp://localhost:%s/d.txt\n' "$dir/b" "$port" |
+    "$ripdrag" --socket stdin --from-stdin &
+pids="$pids $!"
+retry lists stdin "$dir/b" "http://localhost:$port/d.txt"
+
+send args quit >/dev/null
+send stdin quit >/dev/null
+echo "URIs OK"
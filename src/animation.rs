@@ -0,0 +1,381 @@
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::time::{Duration, SystemTime};
+
+use gtk::gdk;
+use gtk::gdk_pixbuf::{InterpType, PixbufAnimation};
+use gtk::gio;
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use gtk::subclass::prelude::*;
+
+use crate::scheduler::Thumbnail;
+use crate::ARGS;
+
+/// Types that may hold more than one frame. Whether a file is animated is read from its
+/// headers before it is decoded.
+const ANIMATED_TYPES: &[&str] = &["image/gif", "image/png", "image/apng", "image/webp"];
+
+/// Longer animations are cut, so they don't take too much memory.
+const MAX_FRAMES: u32 = 120;
+
+/// A frame of an animation, scaled to the size of the thumbnails.
+pub struct Frame {
+    thumbnail: Thumbnail,
+    /// None for the last frame of animations that don't loop
+    delay: Option<Duration>,
+}
+
+glib::wrapper! {
+    /// Plays an animated image at the size of the icons.
+    pub struct Animation(ObjectSubclass<imp::Animation>)
+        @implements gdk::Paintable;
+}
+
+impl Animation {
+    /// Must be given at least one frame.
+    pub fn new(frames: Vec<Frame>) -> Self {
+        let obj: Self = glib::Object::new();
+        let imp = obj.imp();
+        let frames: Vec<(gdk::Texture, Option<Duration>)> = frames
+            .into_iter()
+            .map(|frame| (frame.thumbnail.texture(), frame.delay))
+            .collect();
+        // frames are rendered for the scale of the window, but shown at the size of the icons
+        let icon_size = ARGS.get().unwrap().icon_size;
+        let (width, height) = (frames[0].0.width(), frames[0].0.height());
+        imp.size
+            .set((icon_size, (height * icon_size / width.max(1)).max(1)));
+        imp.frames.replace(frames);
+        obj
+    }
+
+    /// Starts or resumes the animation.
+    pub fn play(&self) {
+        if self.imp().timeout.borrow().is_none() {
+            self.schedule();
+        }
+    }
+
+    /// Stops at the current frame.
+    pub fn pause(&self) {
+        if let Some(timeout) = self.imp().timeout.take() {
+            timeout.remove();
+        }
+    }
+
+    /// Shows the next frame once the current one has been shown long enough.
+    fn schedule(&self) {
+        let imp = self.imp();
+        let delay = imp
+            .frames
+            .borrow()
+            .get(imp.current.get())
+            .and_then(|(_, delay)| *delay);
+        // the last frame of animations that don't loop has no delay
+        let Some(delay) = delay else {
+            return;
+        };
+        let timeout = glib::timeout_add_local_once(
+            delay,
+            clone!(
+                #[weak(rename_to = obj)]
+                self,
+                move || {
+                    let imp = obj.imp();
+                    imp.timeout.take();
+                    let count = imp.frames.borrow().len();
+                    imp.current.set((imp.current.get() + 1) % count.max(1));
+                    obj.invalidate_contents();
+                    obj.schedule();
+                }
+            ),
+        );
+        imp.timeout.replace(Some(timeout));
+    }
+}
+
+/// Whether the file may be animated.
+pub fn is_animatable(content_type: &str) -> bool {
+    ANIMATED_TYPES
+        .iter()
+        .any(|mime_type| gio::content_type_is_mime_type(content_type, mime_type))
+}
+
+fn read_bytes<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// APNGs have an `acTL` chunk with the number of frames before the image data.
+fn png_frames(reader: &mut BufReader<impl Read + Seek>) -> io::Result<u32> {
+    reader.seek(SeekFrom::Start(8))?;
+    loop {
+        let [l0, l1, l2, l3, t0, t1, t2, t3] = read_bytes(reader)?;
+        let length = u32::from_be_bytes([l0, l1, l2, l3]);
+        match &[t0, t1, t2, t3] {
+            b"acTL" => return Ok(u32::from_be_bytes(read_bytes(reader)?)),
+            b"IDAT" | b"IEND" => return Ok(1),
+            // the chunk data is followed by a CRC
+            _ => reader.seek_relative(i64::from(length) + 4)?,
+        }
+    }
+}
+
+/// Skips the sub-blocks of a GIF, which end with an empty one.
+fn skip_gif_sub_blocks(reader: &mut BufReader<impl Read + Seek>) -> io::Result<()> {
+    loop {
+        let [size] = read_bytes(reader)?;
+        if size == 0 {
+            return Ok(());
+        }
+        reader.seek_relative(i64::from(size))?;
+    }
+}
+
+/// Size of the color table announced by the flags of a GIF header or image descriptor.
+fn gif_color_table(flags: u8) -> i64 {
+    if flags & 0x80 == 0 {
+        0
+    } else {
+        3 << ((flags & 0x07) + 1)
+    }
+}
+
+/// Counts the image descriptors of a GIF, up to MAX_FRAMES.
+fn gif_frames(reader: &mut BufReader<impl Read + Seek>) -> io::Result<u32> {
+    reader.seek(SeekFrom::Start(10))?;
+    let [flags, _, _] = read_bytes(reader)?;
+    reader.seek_relative(gif_color_table(flags))?;
+    let mut frames = 0;
+    while frames < MAX_FRAMES {
+        match read_bytes(reader)? {
+            // extension: label and sub-blocks
+            [0x21] => {
+                reader.seek_relative(1)?;
+                skip_gif_sub_blocks(reader)?;
+            }
+            // image: descriptor, color table, LZW code size and sub-blocks
+            [0x2c] => {
+                frames += 1;
+                let descriptor: [u8; 9] = read_bytes(reader)?;
+                reader.seek_relative(gif_color_table(descriptor[8]) + 1)?;
+                skip_gif_sub_blocks(reader)?;
+            }
+            _ => break,
+        }
+    }
+    Ok(frames)
+}
+
+/// Counts the `ANMF` chunks of a WebP, up to MAX_FRAMES.
+fn webp_frames(reader: &mut BufReader<impl Read + Seek>) -> io::Result<u32> {
+    reader.seek(SeekFrom::Start(12))?;
+    let mut frames = 0;
+    while frames < MAX_FRAMES {
+        let Ok([f0, f1, f2, f3, s0, s1, s2, s3]) = read_bytes(reader) else {
+            break;
+        };
+        if &[f0, f1, f2, f3] == b"ANMF" {
+            frames += 1;
+        }
+        let size = u32::from_le_bytes([s0, s1, s2, s3]);
+        // chunks are padded to an even size
+        reader.seek_relative(i64::from(size) + i64::from(size % 2))?;
+    }
+    Ok(frames)
+}
+
+/// How many frames the image has, read from its headers without decoding it.
+/// Still images and other formats have one.
+fn frame_count(reader: impl Read + Seek) -> io::Result<u32> {
+    let mut reader = BufReader::new(reader);
+    let magic: [u8; 12] = read_bytes(&mut reader)?;
+    if magic.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_frames(&mut reader)
+    } else if magic.starts_with(b"GIF8") {
+        gif_frames(&mut reader)
+    } else if magic.starts_with(b"RIFF") && &magic[8..] == b"WEBP" {
+        webp_frames(&mut reader)
+    } else {
+        Ok(1)
+    }
+}
+
+/// Decodes the frames of an animated image, SIZE pixels wide. Returns None when the image
+/// has a single frame or can't be decoded. Which formats can be animated depends on the
+/// installed gdk-pixbuf loaders. Blocking.
+pub fn decode(file: &gio::File, size: i32) -> Option<Vec<Frame>> {
+    let frame_count = frame_count(file.read(gio::Cancellable::NONE).ok()?.into_read())
+        .unwrap_or(1)
+        .min(MAX_FRAMES);
+    if frame_count < 2 {
+        return None;
+    }
+    let stream = file.read(gio::Cancellable::NONE).ok()?;
+    let animation = PixbufAnimation::from_stream(&stream, gio::Cancellable::NONE)
+        .map_err(|err| eprintln!("{}: {}", file.parse_name(), err))
+        .ok()?;
+    if animation.is_static_image() {
+        return None;
+    }
+    let height = (animation.height() * size / animation.width().max(1)).max(1);
+
+    // the frames are stepped through by advancing the time by their delays
+    let start = SystemTime::now();
+    let iter = animation.iter(Some(start));
+    let mut elapsed = Duration::ZERO;
+    let mut frames = Vec::with_capacity(frame_count as usize);
+    for _ in 0..frame_count {
+        let delay = iter.delay_time();
+        let image = iter
+            .pixbuf()
+            .scale_simple(size, height, InterpType::Bilinear)?;
+        frames.push(Frame {
+            thumbnail: Thumbnail::new(&image),
+            delay,
+        });
+        let Some(delay) = delay else {
+            break;
+        };
+        elapsed += delay;
+        iter.advance(start + elapsed);
+    }
+    (frames.len() > 1).then_some(frames)
+}
+
+mod imp {
+    use std::cell::{Cell, RefCell};
+
+    use super::*;
+
+    #[derive(Default)]
+    pub struct Animation {
+        /// The frames, rendered for the scale of the window, and their delays
+        pub frames: RefCell<Vec<(gdk::Texture, Option<Duration>)>>,
+        pub current: Cell<usize>,
+        /// Size at which the animation is shown
+        pub size: Cell<(i32, i32)>,
+        /// Set while the animation is playing
+        pub timeout: RefCell<Option<glib::SourceId>>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for Animation {
+        const NAME: &'static str = "RipDragAnimation";
+        type Type = super::Animation;
+        type Interfaces = (gdk::Paintable,);
+    }
+
+    impl ObjectImpl for Animation {
+        fn dispose(&self) {
+            self.obj().pause();
+        }
+    }
+
+    impl PaintableImpl for Animation {
+        fn intrinsic_width(&self) -> i32 {
+            self.size.get().0
+        }
+
+        fn intrinsic_height(&self) -> i32 {
+            self.size.get().1
+        }
+
+        fn snapshot(&self, snapshot: &gdk::Snapshot, width: f64, height: f64) {
+            if let Some((frame, _)) = self.frames.borrow().get(self.current.get()) {
+                frame.snapshot(snapshot, width, height);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn gif(frames: usize) -> Vec<u8> {
+        // 1x1 with a global color table of two colors
+        let mut gif = b"GIF89a\x01\x00\x01\x00\x80\x00\x00".to_vec();
+        gif.extend([0; 6]);
+        for _ in 0..frames {
+            // graphic control extension, image descriptor and image data
+            gif.extend(b"\x21\xf9\x04\x00\x0a\x00\x00\x00");
+            gif.extend(b"\x2c\x00\x00\x00\x00\x01\x00\x01\x00\x00");
+            gif.extend(b"\x02\x02\x44\x01\x00");
+        }
+        gif.push(0x3b);
+        gif
+    }
+
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend(kind);
+        chunk.extend(data);
+        chunk.extend([0; 4]);
+        chunk
+    }
+
+    fn png(frames: Option<u32>) -> Vec<u8> {
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend(png_chunk(b"IHDR", &[0; 13]));
+        if let Some(frames) = frames {
+            let mut actl = frames.to_be_bytes().to_vec();
+            actl.extend([0; 4]);
+            png.extend(png_chunk(b"acTL", &actl));
+        }
+        png.extend(png_chunk(b"IDAT", &[0; 5]));
+        png.extend(png_chunk(b"IEND", &[]));
+        png
+    }
+
+    fn webp(frames: usize) -> Vec<u8> {
+        let mut chunks = b"VP8X\x0a\x00\x00\x00".to_vec();
+        chunks.extend([0; 10]);
+        for _ in 0..frames {
+            // odd sizes are padded
+            chunks.extend(b"ANMF\x03\x00\x00\x00\x00\x00\x00\x00");
+        }
+        let mut webp = b"RIFF".to_vec();
+        webp.extend((chunks.len() as u32 + 4).to_le_bytes());
+        webp.extend(b"WEBP");
+        webp.extend(chunks);
+        webp
+    }
+
+    fn count(bytes: Vec<u8>) -> u32 {
+        frame_count(Cursor::new(bytes)).unwrap()
+    }
+
+    #[test]
+    fn gif_frames() {
+        assert_eq!(count(gif(1)), 1);
+        assert_eq!(count(gif(3)), 3);
+        assert_eq!(count(gif(MAX_FRAMES as usize + 5)), MAX_FRAMES);
+    }
+
+    #[test]
+    fn png_frames() {
+        assert_eq!(count(png(None)), 1);
+        assert_eq!(count(png(Some(4))), 4);
+    }
+
+    #[test]
+    fn webp_frames() {
+        assert_eq!(count(webp(0)), 0);
+        assert_eq!(count(webp(2)), 2);
+
+        // the size of the chunk is larger than the file
+        let mut broken = webp(0);
+        broken.extend(b"ANMF\xff\xff\xff\xff");
+        assert_eq!(count(broken), 1);
+    }
+
+    #[test]
+    fn other_formats() {
+        assert_eq!(count(b"\xff\xd8\xff\xe0 not animated".to_vec()), 1);
+        assert!(frame_count(Cursor::new(b"GIF".to_vec())).is_err());
+    }
+}
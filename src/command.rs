@@ -0,0 +1,146 @@
+use std::str::FromStr;
+
+use gtk::gio::{self, ListStore};
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use gtk::{ApplicationWindow, MultiSelection};
+
+use crate::file_object::FileObject;
+use crate::util::find_file;
+
+/// A line read from stdin with --stdin-commands.
+/// Everything after the first space is the argument, so paths may contain spaces.
+#[derive(Debug)]
+pub enum Command {
+    /// `add PATH` appends a file, JSON input may also override its name and icon
+    Add {
+        file: gio::File,
+        name: Option<String>,
+        icon: Option<String>,
+    },
+    /// `remove PATH` removes a file
+    Remove(gio::File),
+    /// `clear` removes all files
+    Clear,
+    /// `select PATH` adds a file to the selection, `select` alone clears it
+    Select(Option<gio::File>),
+    /// `title TEXT` sets the window title
+    Title(String),
+    /// `list` prints every file, or sends them back over the socket
+    List(Option<async_channel::Sender<Vec<String>>>),
+    /// `quit` closes ripdrag
+    Quit,
+}
+
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let (name, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let path = || {
+            if argument.is_empty() {
+                Err(format!("{}: Missing path", name))
+            } else {
+                Ok(gio::File::for_commandline_arg(argument))
+            }
+        };
+        match name {
+            "add" => Ok(Command::Add {
+                file: path()?,
+                name: None,
+                icon: None,
+            }),
+            "remove" => Ok(Command::Remove(path()?)),
+            "clear" => Ok(Command::Clear),
+            "select" => Ok(Command::Select(path().ok())),
+            "title" => Ok(Command::Title(argument.to_string())),
+            "list" => Ok(Command::List(None)),
+            "quit" => Ok(Command::Quit),
+            _ => Err(format!("{}: Unknown command", name)),
+        }
+    }
+}
+
+impl Command {
+    /// Checks that an added file exists. This may block, so it is done before sending the
+    /// command to the main thread.
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Command::Add { file, .. } if !file.query_exists(gio::Cancellable::NONE) => {
+                Err(format!("{} does not exist!", file.parse_name()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Applies the command to the window. Selecting does nothing with --all-compact.
+    pub fn run(
+        self,
+        model: &ListStore,
+        selection: Option<&MultiSelection>,
+        window: &ApplicationWindow,
+    ) {
+        match self {
+            Command::Add { file, name, icon } => {
+                model.append(&FileObject::with_overrides(&file, name, icon))
+            }
+            Command::Remove(file) => match find_file(model, &file) {
+                Some(position) => model.remove(position),
+                None => eprintln!("{}: Not in the list", file.parse_name()),
+            },
+            Command::Clear => model.remove_all(),
+            Command::Select(file) => {
+                let Some(selection) = selection else {
+                    return;
+                };
+                match file {
+                    Some(file) => match find_file(model, &file) {
+                        Some(position) => {
+                            selection.select_item(position, false);
+                        }
+                        None => eprintln!("{}: Not in the list", file.parse_name()),
+                    },
+                    None => {
+                        selection.unselect_all();
+                    }
+                }
+            }
+            Command::Title(title) => window.set_title(Some(&title)),
+            Command::List(reply) => {
+                let paths: Vec<String> = model
+                    .iter::<FileObject>()
+                    .flatten()
+                    .map(|file_object| file_object.file().parse_name().to_string())
+                    .collect();
+                match reply {
+                    Some(reply) => {
+                        let _ = reply.try_send(paths);
+                    }
+                    None => paths.iter().for_each(|path| println!("{}", path)),
+                }
+            }
+            Command::Quit => std::process::exit(0),
+        }
+    }
+}
+
+/// Runs the commands sent from other threads on the main thread.
+pub fn run_commands(
+    receiver: async_channel::Receiver<Command>,
+    model: &ListStore,
+    selection: Option<&MultiSelection>,
+    window: &ApplicationWindow,
+) {
+    let selection = selection.cloned();
+    glib::spawn_future_local(clone!(
+        #[weak]
+        model,
+        #[weak]
+        window,
+        async move {
+            while let Ok(command) = receiver.recv().await {
+                command.run(&model, selection.as_ref(), &window);
+            }
+        }
+    ));
+}
@@ -1,4 +1,3 @@
-use crate::ARGS;
 use glib::Object;
 use glib_macros::clone;
 use gtk::gio::ListStore;
@@ -6,16 +5,14 @@ use gtk::prelude::*;
 use gtk::subclass::prelude::*;
 use gtk::{glib, CssProvider, DragSource, Label, Widget};
 
-use crate::util::{drag_source_and_exit, generate_file_model, setup_drag_source_all, ListWidget};
+use crate::util::{generate_file_model, setup_drag_end, setup_drag_source_all, ListWidget};
 
 pub fn generate_compact_view() -> ListWidget {
     let file_model = generate_file_model();
 
     let drag_source = DragSource::new();
     setup_drag_source_all(&drag_source, &file_model);
-    if ARGS.get().unwrap().and_exit {
-        drag_source_and_exit(&drag_source);
-    }
+    setup_drag_end(&drag_source, &file_model);
 
     let obj = CompactLabel::new(file_model);
     let model = obj.model();
@@ -37,6 +34,7 @@ pub fn generate_compact_view() -> ListWidget {
     ListWidget {
         list_model: model,
         widget: obj,
+        selection: None,
     }
 }
 
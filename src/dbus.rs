@@ -0,0 +1,190 @@
+use gtk::gio::{self, File, ListStore};
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+use gtk::Application;
+
+use crate::expand;
+use crate::file_object::FileObject;
+use crate::output::{action_name, DragOutcome};
+use crate::util::find_file;
+use crate::ARGS;
+
+const INTERFACE: &str = "it.catboy.ripdrag.Window";
+
+/// Files are passed as URIs or absolute paths and returned as URIs.
+const INTROSPECTION: &str = r#"
+<node>
+  <interface name="it.catboy.ripdrag.Window">
+    <method name="AddFiles">
+      <arg type="as" name="files" direction="in"/>
+    </method>
+    <method name="RemoveFiles">
+      <arg type="as" name="files" direction="in"/>
+    </method>
+    <method name="ListFiles">
+      <arg type="as" name="files" direction="out"/>
+    </method>
+    <method name="Clear"/>
+    <method name="Quit"/>
+    <signal name="FileDropped">
+      <arg type="as" name="files"/>
+    </signal>
+    <signal name="DragFinished">
+      <arg type="s" name="status"/>
+      <arg type="s" name="action"/>
+      <arg type="as" name="files"/>
+    </signal>
+  </interface>
+</node>
+"#;
+
+fn uris(files: &[File]) -> Vec<String> {
+    files.iter().map(|file| file.uri().to_string()).collect()
+}
+
+/// Checks the files without blocking the window, as remote ones can take a while.
+async fn add_files(
+    model: &ListStore,
+    files: Vec<String>,
+) -> Result<Option<glib::Variant>, glib::Error> {
+    let files: Vec<File> = files.iter().map(File::for_commandline_arg).collect();
+    for file in &files {
+        file.query_info_future(
+            gio::FILE_ATTRIBUTE_STANDARD_TYPE,
+            gio::FileQueryInfoFlags::NONE,
+            glib::Priority::DEFAULT,
+        )
+        .await
+        .map_err(|err| {
+            glib::Error::new(
+                gio::IOErrorEnum::NotFound,
+                &format!("{}: {}", file.parse_name(), err.message()),
+            )
+        })?;
+    }
+    if expand::is_active() {
+        expand::stream_into(model, files);
+    } else {
+        let files: Vec<FileObject> = files.iter().map(FileObject::new).collect();
+        model.extend_from_slice(&files);
+    }
+    Ok(None)
+}
+
+fn remove_files(
+    model: &ListStore,
+    files: Vec<String>,
+) -> Result<Option<glib::Variant>, glib::Error> {
+    for file in files.iter().map(File::for_commandline_arg) {
+        if let Some(position) = find_file(model, &file) {
+            model.remove(position);
+        }
+    }
+    Ok(None)
+}
+
+fn list_files(model: &ListStore) -> Result<Option<glib::Variant>, glib::Error> {
+    let files: Vec<File> = model
+        .iter::<FileObject>()
+        .flatten()
+        .map(|file_object| file_object.file())
+        .collect();
+    Ok(Some((uris(&files),).to_variant()))
+}
+
+/// Exports the `it.catboy.ripdrag.Window` interface next to the ones of the application.
+/// Without --unique or --instance the application doesn't own a name on the bus,
+/// so `it.catboy.ripdrag.Pid<PID>` is owned instead.
+pub fn export(app: &Application, model: &ListStore) {
+    let (Some(connection), Some(object_path)) = (app.dbus_connection(), app.dbus_object_path())
+    else {
+        eprintln!("Not connected to the session bus");
+        return;
+    };
+    let interface = gio::DBusNodeInfo::for_xml(INTROSPECTION)
+        .ok()
+        .and_then(|node| node.lookup_interface(INTERFACE))
+        .expect("Invalid introspection data");
+
+    let registration = connection
+        .register_object(&object_path, &interface)
+        .method_call(clone!(
+            #[weak]
+            model,
+            move |connection, _, _, _, method, parameters, invocation| {
+                let files = || parameters.get::<(Vec<String>,)>().unwrap_or_default().0;
+                match method {
+                    "AddFiles" => {
+                        let files = files();
+                        glib::spawn_future_local(async move {
+                            invocation.return_result(add_files(&model, files).await);
+                        });
+                    }
+                    "RemoveFiles" => invocation.return_result(remove_files(&model, files())),
+                    "ListFiles" => invocation.return_result(list_files(&model)),
+                    "Clear" => {
+                        model.remove_all();
+                        invocation.return_value(None);
+                    }
+                    "Quit" => {
+                        invocation.return_value(None);
+                        // make sure the reply is sent before exiting
+                        glib::spawn_future_local(async move {
+                            let _ = connection.flush_future().await;
+                            std::process::exit(0);
+                        });
+                    }
+                    _ => unreachable!("Method is not in the introspection data"),
+                }
+            }
+        ))
+        .build();
+    if let Err(err) = registration {
+        eprintln!("{}", err);
+        return;
+    }
+
+    let args = ARGS.get().unwrap();
+    if !args.unique && args.instance.is_none() {
+        gio::bus_own_name_on_connection(
+            &connection,
+            &format!("it.catboy.ripdrag.Pid{}", std::process::id()),
+            gio::BusNameOwnerFlags::NONE,
+            |_, _| {},
+            |_, name| eprintln!("Could not own {} on the session bus", name),
+        );
+    }
+}
+
+fn emit(signal: &str, parameters: glib::Variant) {
+    if !ARGS.get().unwrap().dbus {
+        return;
+    }
+    let Some(app) = gio::Application::default() else {
+        return;
+    };
+    if let (Some(connection), Some(object_path)) = (app.dbus_connection(), app.dbus_object_path()) {
+        let result =
+            connection.emit_signal(None, &object_path, INTERFACE, signal, Some(&parameters));
+        if let Err(err) = result {
+            eprintln!("{}", err);
+        }
+    }
+}
+
+/// Emits FileDropped with --dbus.
+pub fn emit_file_dropped(files: &[File]) {
+    emit("FileDropped", (uris(files),).to_variant());
+}
+
+/// Emits DragFinished with --dbus.
+pub fn emit_drag_finished(files: &[File], outcome: DragOutcome) {
+    let action = match outcome {
+        DragOutcome::Dropped(action) => action,
+        _ => gtk::gdk::DragAction::empty(),
+    };
+    emit(
+        "DragFinished",
+        (outcome.name(), action_name(action), uris(files)).to_variant(),
+    );
+}
@@ -0,0 +1,392 @@
+use gtk::gio::{self, File, FileType, ListStore};
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+
+use crate::file_object::FileObject;
+use crate::ARGS;
+
+/// Files found while expanding are sent to the model in batches of this size.
+const BATCH_SIZE: usize = 256;
+
+/// Matches a glob against a path: `*` and `?` don't match `/`, `**` does, `[...]` matches a
+/// set of characters, e.g. `[a-z]` or `[!0-9]`. Like in git, `**/` also matches no directory
+/// at all, so `**/build` matches `build` too.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern {
+            [] => text.is_empty(),
+            ['*', '*', '/', rest @ ..] => {
+                matches(rest, text)
+                    || (1..=text.len()).any(|i| text[i - 1] == '/' && matches(rest, &text[i..]))
+            }
+            ['*', '*', rest @ ..] => (0..=text.len()).any(|i| matches(rest, &text[i..])),
+            ['*', rest @ ..] => (0..=text.len())
+                .take_while(|&i| i == 0 || text[i - 1] != '/')
+                .any(|i| matches(rest, &text[i..])),
+            ['?', rest @ ..] => {
+                matches!(text.first(), Some(c) if *c != '/') && matches(rest, &text[1..])
+            }
+            ['[', rest @ ..] => {
+                let Some(end) = rest.iter().skip(1).position(|c| *c == ']').map(|i| i + 1) else {
+                    return text.first() == Some(&'[') && matches(rest, &text[1..]);
+                };
+                let Some(c) = text.first() else {
+                    return false;
+                };
+                let (negated, set) = match &rest[..end] {
+                    ['!', set @ ..] | ['^', set @ ..] => (true, set),
+                    set => (false, set),
+                };
+                let mut found = false;
+                let mut i = 0;
+                while i < set.len() {
+                    if i + 2 < set.len() && set[i + 1] == '-' {
+                        found |= (set[i]..=set[i + 2]).contains(c);
+                        i += 3;
+                    } else {
+                        found |= set[i] == *c;
+                        i += 1;
+                    }
+                }
+                found != negated && *c != '/' && matches(&rest[end + 1..], &text[1..])
+            }
+            [p, rest @ ..] => text.first() == Some(p) && matches(rest, &text[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    matches(&pattern, &text)
+}
+
+/// Globs without a slash match the file name, the others the path relative to BASE.
+fn glob_matches_file(pattern: &str, file: &File, base: &File) -> bool {
+    if pattern.contains('/') {
+        base.relative_path(file)
+            .is_some_and(|path| glob_match(pattern, &path.to_string_lossy()))
+    } else {
+        file.basename()
+            .is_some_and(|name| glob_match(pattern, &name.to_string_lossy()))
+    }
+}
+
+/// Whether directories are expanded or files filtered at all.
+pub fn is_active() -> bool {
+    let args = ARGS.get().unwrap();
+    args.recursive || !args.include.is_empty() || !args.exclude.is_empty()
+}
+
+fn is_excluded(file: &File, base: &File) -> bool {
+    ARGS.get()
+        .unwrap()
+        .exclude
+        .iter()
+        .any(|pattern| glob_matches_file(pattern, file, base))
+}
+
+/// Whether a file passes --include and --exclude, with paths relative to BASE.
+pub fn is_included(file: &File, base: &File) -> bool {
+    let include = &ARGS.get().unwrap().include;
+    (include.is_empty()
+        || include
+            .iter()
+            .any(|pattern| glob_matches_file(pattern, file, base)))
+        && !is_excluded(file, base)
+}
+
+/// A line of a .gitignore file.
+struct IgnoreRule {
+    /// The directory of the .gitignore, patterns with a slash are relative to it
+    base: File,
+    pattern: String,
+    anchored: bool,
+    negated: bool,
+    dir_only: bool,
+}
+
+impl IgnoreRule {
+    fn parse(base: &File, line: &str) -> Option<Self> {
+        let line = line.trim_end();
+        if line.is_empty() || line.starts_with('#') {
+            return None;
+        }
+        let (negated, line) = match line.strip_prefix('!') {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix('\\').unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix('/') {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains('/');
+        Some(IgnoreRule {
+            base: base.clone(),
+            pattern: line.trim_start_matches('/').to_string(),
+            anchored,
+            negated,
+            dir_only,
+        })
+    }
+
+    fn matches(&self, file: &File, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        if self.anchored {
+            self.base
+                .relative_path(file)
+                .is_some_and(|path| glob_match(&self.pattern, &path.to_string_lossy()))
+        } else {
+            file.basename()
+                .is_some_and(|name| glob_match(&self.pattern, &name.to_string_lossy()))
+        }
+    }
+}
+
+/// Reads the .gitignore of a directory, if there is one.
+fn read_gitignore(dir: &File) -> Vec<IgnoreRule> {
+    let Ok((contents, _)) = dir
+        .child(".gitignore")
+        .load_contents(gio::Cancellable::NONE)
+    else {
+        return Vec::new();
+    };
+    String::from_utf8_lossy(&contents)
+        .lines()
+        .filter_map(|line| IgnoreRule::parse(dir, line))
+        .collect()
+}
+
+/// The last matching rule decides, so a later `!pattern` can bring a file back.
+fn is_ignored(rules: &[IgnoreRule], file: &File, is_dir: bool) -> bool {
+    rules
+        .iter()
+        .rev()
+        .find(|rule| rule.matches(file, is_dir))
+        .is_some_and(|rule| !rule.negated)
+}
+
+/// Walks a directory inside BASE, calling `found` for every file that passes the filters.
+fn walk(
+    dir: &File,
+    base: &File,
+    depth: u32,
+    rules: &mut Vec<IgnoreRule>,
+    found: &mut dyn FnMut(File),
+) {
+    let args = ARGS.get().unwrap();
+    let rule_count = rules.len();
+    if !args.no_ignore {
+        rules.extend(read_gitignore(dir));
+    }
+    let children = dir.enumerate_children(
+        &format!(
+            "{},{},{}",
+            gio::FILE_ATTRIBUTE_STANDARD_NAME,
+            gio::FILE_ATTRIBUTE_STANDARD_TYPE,
+            gio::FILE_ATTRIBUTE_STANDARD_IS_HIDDEN
+        ),
+        gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+        gio::Cancellable::NONE,
+    );
+    let children = match children {
+        Ok(children) => children,
+        Err(err) => {
+            eprintln!("{}: {}", dir.parse_name(), err);
+            return;
+        }
+    };
+    // sorted, so the order doesn't depend on the file system
+    let mut infos: Vec<gio::FileInfo> = children.flatten().collect();
+    infos.sort_by_key(|info| info.name());
+    for info in infos {
+        let child = dir.child(info.name());
+        let is_dir = info.file_type() == FileType::Directory;
+        if (info.is_hidden() && !args.hidden)
+            || (!args.no_ignore && (info.name() == *".git" || is_ignored(rules, &child, is_dir)))
+        {
+            continue;
+        }
+        if is_dir {
+            if !is_excluded(&child, base)
+                && args.max_depth.is_none_or(|max_depth| depth < max_depth)
+            {
+                walk(&child, base, depth + 1, rules, found);
+            }
+        } else if is_included(&child, base) {
+            found(child);
+        }
+    }
+    rules.truncate(rule_count);
+}
+
+/// Calls `found` for the file or, with --recursive, for the files inside the directory.
+/// Globs with a slash match paths relative to the directory, or to the current directory
+/// for files that are not expanded. Blocking.
+pub fn expand(file: &File, found: &mut dyn FnMut(File)) {
+    let is_dir = file.query_file_type(gio::FileQueryInfoFlags::NONE, gio::Cancellable::NONE)
+        == FileType::Directory;
+    if ARGS.get().unwrap().recursive && is_dir {
+        walk(file, file, 1, &mut Vec::new(), found);
+    } else if is_included(file, &File::for_path(".")) {
+        found(file.clone());
+    }
+}
+
+/// Expands the files in the background, adding the results to the model as they are found.
+pub fn stream_into(model: &ListStore, files: Vec<File>) {
+    let (sender, receiver) = async_channel::bounded(1);
+    gio::spawn_blocking(move || {
+        let mut batch = Vec::with_capacity(BATCH_SIZE);
+        for file in &files {
+            expand(file, &mut |file| {
+                batch.push(file);
+                if batch.len() == BATCH_SIZE {
+                    let _ = sender.send_blocking(std::mem::take(&mut batch));
+                }
+            });
+        }
+        let _ = sender.send_blocking(batch);
+    });
+
+    glib::spawn_future_local(clone!(
+        #[weak]
+        model,
+        async move {
+            while let Ok(files) = receiver.recv().await {
+                let files: Vec<FileObject> = files.iter().map(FileObject::new).collect();
+                model.extend_from_slice(&files);
+            }
+        }
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn star_stops_at_slash() {
+        assert!(glob_match("*.txt", "notes.txt"));
+        assert!(!glob_match("*.txt", "dir/notes.txt"));
+        assert!(glob_match("dir/*", "dir/notes.txt"));
+        assert!(!glob_match("dir/*", "dir/sub/notes.txt"));
+        assert!(glob_match("?.rs", "a.rs"));
+        assert!(!glob_match("a?b", "a/b"));
+    }
+
+    #[test]
+    fn double_star() {
+        assert!(glob_match("**.txt", "dir/sub/notes.txt"));
+        assert!(glob_match("dir/**", "dir/sub/notes.txt"));
+        assert!(glob_match("**/build", "build"));
+        assert!(glob_match("**/build", "src/build"));
+        assert!(glob_match("**/build", "src/sub/build"));
+        assert!(!glob_match("**/build", "rebuild"));
+        assert!(glob_match("a/**/b", "a/b"));
+        assert!(glob_match("a/**/b", "a/x/y/b"));
+        assert!(!glob_match("a/**/b", "ab"));
+    }
+
+    #[test]
+    fn character_sets() {
+        assert!(glob_match("[a-c]x", "bx"));
+        assert!(!glob_match("[a-c]x", "dx"));
+        assert!(glob_match("[!a-z]*", "1st"));
+        assert!(!glob_match("[!a-z]*", "first"));
+        assert!(glob_match("[^a-z]*", "1st"));
+        assert!(!glob_match("[!a-z]", "/"));
+        assert!(glob_match("[]]", "]"));
+    }
+
+    #[test]
+    fn unterminated_set_is_literal() {
+        assert!(glob_match("[abc", "[abc"));
+        assert!(!glob_match("[abc", "a"));
+        assert!(glob_match("x[", "x["));
+    }
+
+    #[test]
+    fn globs_with_slash_are_relative() {
+        let base = File::for_path("/home/user/project");
+        let pdf = File::for_path("/home/user/project/docs/manual.pdf");
+        assert!(glob_matches_file("docs/*.pdf", &pdf, &base));
+        assert!(glob_matches_file("**/*.pdf", &pdf, &base));
+        assert!(glob_matches_file("*.pdf", &pdf, &base));
+        assert!(!glob_matches_file("*/docs/*.pdf", &pdf, &base));
+        assert!(!glob_matches_file(
+            "docs/*.pdf",
+            &pdf,
+            &File::for_path("/home")
+        ));
+        assert!(!glob_matches_file(
+            "docs/*.pdf",
+            &pdf,
+            &File::for_path("/elsewhere")
+        ));
+    }
+
+    fn rule(line: &str) -> IgnoreRule {
+        IgnoreRule::parse(&File::for_path("/repo"), line).unwrap()
+    }
+
+    #[test]
+    fn ignore_comments_and_blank_lines() {
+        let base = File::for_path("/repo");
+        assert!(IgnoreRule::parse(&base, "").is_none());
+        assert!(IgnoreRule::parse(&base, "   ").is_none());
+        assert!(IgnoreRule::parse(&base, "# comment").is_none());
+        assert_eq!(rule("\\#file").pattern, "#file");
+    }
+
+    #[test]
+    fn ignore_negation() {
+        let keep = rule("!keep.log");
+        assert!(keep.negated);
+        assert_eq!(keep.pattern, "keep.log");
+        assert!(!rule("\\!file").negated);
+
+        let rules = vec![rule("*.log"), keep];
+        assert!(is_ignored(
+            &rules,
+            &File::for_path("/repo/debug.log"),
+            false
+        ));
+        assert!(!is_ignored(
+            &rules,
+            &File::for_path("/repo/keep.log"),
+            false
+        ));
+    }
+
+    #[test]
+    fn ignore_anchoring() {
+        let anchored = rule("/build");
+        assert!(anchored.anchored);
+        assert!(anchored.matches(&File::for_path("/repo/build"), true));
+        assert!(!anchored.matches(&File::for_path("/repo/src/build"), true));
+
+        let unanchored = rule("build");
+        assert!(!unanchored.anchored);
+        assert!(unanchored.matches(&File::for_path("/repo/src/build"), true));
+
+        let nested = rule("doc/*.html");
+        assert!(nested.anchored);
+        assert!(nested.matches(&File::for_path("/repo/doc/index.html"), false));
+        assert!(!nested.matches(&File::for_path("/repo/src/doc/index.html"), false));
+
+        let any_level = rule("**/build");
+        assert!(any_level.matches(&File::for_path("/repo/build"), true));
+        assert!(any_level.matches(&File::for_path("/repo/src/build"), true));
+    }
+
+    #[test]
+    fn ignore_directories_only() {
+        let rule = rule("target/");
+        assert!(rule.dir_only);
+        assert!(!rule.anchored);
+        assert_eq!(rule.pattern, "target");
+        assert!(rule.matches(&File::for_path("/repo/target"), true));
+        assert!(rule.matches(&File::for_path("/repo/sub/target"), true));
+        assert!(!rule.matches(&File::for_path("/repo/target"), false));
+    }
+}
@@ -1,121 +1,209 @@
-use gio::FileQueryInfoFlags;
-use glib::{GString, Object, Properties};
+use std::cell::Cell;
+
+use gio::ListStore;
+use glib::{Object, Properties};
 use glib_macros::clone;
-use gtk::gdk_pixbuf::Pixbuf;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{gdk, gdk_pixbuf, gio, glib};
+use gtk::{gdk, gio, glib};
 
+use crate::animation::{self, Animation};
+use crate::scheduler;
 use crate::ARGS;
-glib::wrapper! {
-    pub struct FileObject(ObjectSubclass<imp::FileObject>);
+thread_local! {
+    /// Scale factor of the window, thumbnails are rendered at icon_size times this
+    static SCALE_FACTOR: Cell<i32> = const { Cell::new(1) };
 }
 
-trait MimeType {
-    fn mime_type(&self) -> GString;
+/// Renders the thumbnails again when the window moves to a monitor with another scale.
+pub fn set_scale_factor(model: &ListStore, scale_factor: i32) {
+    if SCALE_FACTOR.replace(scale_factor) != scale_factor {
+        for file_object in model.iter::<FileObject>().flatten() {
+            file_object.reload();
+        }
+    }
 }
 
-impl MimeType for gio::File {
-    fn mime_type(&self) -> GString {
-        let file_type = self.query_info(
-            gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE,
-            FileQueryInfoFlags::NONE,
-            gio::Cancellable::NONE,
-        );
-
-        file_type
-            .unwrap_or_default()
-            .content_type()
-            .unwrap_or(GString::format(format_args!("text/plain")))
-    }
+glib::wrapper! {
+    pub struct FileObject(ObjectSubclass<imp::FileObject>);
 }
 
 impl FileObject {
     pub fn new(file: &gio::File) -> Self {
-        let obj = Object::builder().property("file", file);
-        let icon_name = gio::content_type_get_generic_icon_name(&file.mime_type());
-        // use the default thumbnail
-        let icon = gtk::Image::builder()
-            .icon_name(icon_name.unwrap_or(glib::GString::format(format_args!("text/default"))))
+        Self::with_overrides(file, None, None)
+    }
+
+    /// Shows `display_name` instead of the path and `icon` instead of the thumbnail.
+    /// The icon is either an icon name or the path to an image.
+    pub fn with_overrides(
+        file: &gio::File,
+        display_name: Option<String>,
+        icon: Option<String>,
+    ) -> Self {
+        let thumbnail = gtk::Image::builder()
             .pixel_size(ARGS.get().unwrap().icon_size)
             .build();
-        let obj = obj.property("thumbnail", icon).build();
-        let file = file.clone();
+        Object::builder()
+            .property("file", file)
+            .property("thumbnail", thumbnail)
+            .property("display-name", display_name)
+            .property("icon", icon)
+            .build()
+    }
 
-        // For every image a thumbnail of the image is sent. When it is not an image a None is sent.
-        // There is no thumbnail for GIFs.
-        let (sender, receiver) = async_channel::bounded(1);
+    /// Called when a row shows the file. The icon and the thumbnail are only looked up then,
+    /// so a long list doesn't have to query every file before the window appears.
+    pub fn bind(&self) {
+        let imp = self.imp();
+        imp.bound.set(true);
+        if !imp.loaded.get() {
+            self.load();
+        }
+    }
 
-        gio::spawn_blocking(move || {
-            let print_err = |err| eprintln!("{}", err);
-            if !file.query_exists(gio::Cancellable::NONE) {
-                let _ = sender.send_blocking(None).map_err(print_err);
+    /// Called when the row is reused for another file. A thumbnail that is still waiting
+    /// is dropped from the queue, it is requested again when the file is shown again.
+    /// Animations are dropped with their frames and decoded again when needed.
+    pub fn unbind(&self) {
+        let imp = self.imp();
+        imp.bound.set(false);
+        if let Some(animation) = imp.animation.take() {
+            animation.pause();
+            self.thumbnail().set_paintable(None::<&gdk::Paintable>);
+            imp.loaded.set(false);
+        }
+        if !imp.loaded.get() {
+            if let Some(job) = imp.thumbnail_job.take() {
+                job.cancel();
             }
+        }
+    }
 
-            let mime_type = file.mime_type();
-            // this only works for images
-            if !ARGS.get().unwrap().disable_thumbnails
-                && gio::content_type_is_mime_type(&mime_type, "image/*")
-            {
-                let image = gdk_pixbuf::Pixbuf::from_file_at_scale(
-                    file.path().unwrap(),
-                    ARGS.get().unwrap().icon_size,
-                    -1,
-                    true,
-                );
-                if let Ok(image) = image {
-                    let _ = sender
-                        .send_blocking(Some((
-                            image.read_pixel_bytes(),
-                            image.colorspace(),
-                            image.has_alpha(),
-                            image.bits_per_sample(),
-                            image.width(),
-                            image.height(),
-                            image.rowstride(),
-                        )))
-                        .map_err(print_err);
-                } else {
-                    eprintln!("{}", image.unwrap_err());
-                    let _ = sender.send_blocking(None).map_err(print_err);
-                }
+    /// Loads the icon and thumbnail again, once a download has finished or the file has changed.
+    pub fn reload(&self) {
+        let imp = self.imp();
+        if let Some(job) = imp.thumbnail_job.take() {
+            job.cancel();
+        }
+        if let Some(animation) = imp.animation.take() {
+            animation.pause();
+        }
+        imp.loaded.set(false);
+        if imp.bound.get() {
+            self.load();
+        }
+    }
+
+    /// Sets the icon for the file type and loads a thumbnail, without blocking the main thread.
+    fn load(&self) {
+        if let Some(icon) = self.icon() {
+            if icon.contains('/') {
+                self.thumbnail().set_from_file(Some(icon));
             } else {
-                let _ = sender.send_blocking(None).map_err(print_err);
+                self.thumbnail().set_icon_name(Some(&icon));
             }
-        });
-        // Sets the thumbnail and closes the receiver channel regardless of what was sent.
+            self.imp().loaded.set(true);
+            return;
+        }
 
+        let job = gio::Cancellable::new();
+        self.imp().thumbnail_job.replace(Some(job.clone()));
+        let file = self.file();
+        let obj = self;
         glib::spawn_future_local(clone!(
             #[weak]
             obj,
             async move {
-                if let Ok(Some(image)) = receiver.recv().await {
-                    let obj: FileObject = obj;
-                    let thumbnail = obj.thumbnail();
-                    // (apply gdk_pixbuf::Pixbuf::from_bytes image)
-                    let image = Pixbuf::from_bytes(
-                        &image.0, image.1, image.2, image.3, image.4, image.5, image.6,
-                    );
-                    thumbnail.set_paintable(Some(&gdk::Texture::for_pixbuf(&image)));
+                let content_type = file
+                    .query_info_future(
+                        gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE,
+                        gio::FileQueryInfoFlags::NONE,
+                        glib::Priority::DEFAULT,
+                    )
+                    .await
+                    .ok()
+                    .and_then(|info| info.content_type())
+                    .unwrap_or_else(|| "text/plain".into());
+                if job.is_cancelled() {
+                    return;
+                }
+                // use the default thumbnail
+                let icon_name = gio::content_type_get_generic_icon_name(&content_type);
+                obj.thumbnail()
+                    .set_icon_name(Some(&icon_name.unwrap_or_else(|| "text/default".into())));
+
+                let args = ARGS.get().unwrap();
+                if args.disable_thumbnails {
+                    obj.imp().loaded.set(true);
+                    return;
+                }
+                // the receiver is closed without a reply when the job is cancelled
+                let size = args.icon_size * SCALE_FACTOR.get();
+                if args.animate && animation::is_animatable(&content_type) {
+                    match scheduler::request_animation(&file, &job, size).recv().await {
+                        // the row may have been unbound while the reply was on its way
+                        Ok(Some(_)) if job.is_cancelled() => return,
+                        Ok(Some(frames)) => {
+                            let animation = Animation::new(frames);
+                            obj.thumbnail().set_paintable(Some(&animation));
+                            animation.play();
+                            obj.imp().animation.replace(Some(animation));
+                            obj.imp().loaded.set(true);
+                            return;
+                        }
+                        Ok(None) => {}
+                        Err(_) => return,
+                    }
+                }
+                if let Ok(thumbnail) = scheduler::request(&file, &job, size).recv().await {
+                    // the image shows the texture at icon_size, so on HiDPI screens
+                    // every device pixel is used
+                    if let Some(thumbnail) = thumbnail {
+                        obj.thumbnail().set_paintable(Some(&thumbnail.texture()));
+                    }
+                    obj.imp().loaded.set(true);
                 }
             }
         ));
-        obj
     }
 }
 
 mod imp {
-    use std::cell::RefCell;
+    use std::cell::{Cell, RefCell};
 
     use super::*;
 
     #[derive(Properties)]
     #[properties(wrapper_type = super::FileObject)]
     pub struct FileObject {
-        #[property(get, construct_only)]
+        /// Changes when the file is renamed
+        #[property(get, set)]
         file: RefCell<gio::File>,
         #[property(get, construct_only)]
         thumbnail: RefCell<gtk::Image>,
+        /// Shown instead of the path
+        #[property(get, construct_only, nullable)]
+        display_name: RefCell<Option<String>>,
+        /// Icon name or path to an image, shown instead of the thumbnail
+        #[property(get, construct_only, nullable)]
+        icon: RefCell<Option<String>>,
+        /// Fraction of the file that has been downloaded
+        #[property(get, set, minimum = 0.0, maximum = 1.0)]
+        progress: Cell<f64>,
+        /// Set while the file is being downloaded, cancelling it aborts the download
+        #[property(get, set, nullable)]
+        cancellable: RefCell<Option<gio::Cancellable>>,
+        /// Watches the file for deletion, renames and modifications
+        #[property(get, set, nullable)]
+        monitor: RefCell<Option<gio::FileMonitor>>,
+        /// Cancels the thumbnail while it is waiting to be loaded
+        pub thumbnail_job: RefCell<Option<gio::Cancellable>>,
+        /// Whether a row shows the file
+        pub bound: Cell<bool>,
+        /// Whether the icon and thumbnail have been loaded
+        pub loaded: Cell<bool>,
+        /// Plays while a row shows the file, with --animate
+        pub animation: RefCell<Option<Animation>>,
     }
 
     impl Default for FileObject {
@@ -123,6 +211,15 @@ mod imp {
             Self {
                 file: RefCell::new(gio::File::for_path("/does-not-exist")),
                 thumbnail: RefCell::new(gtk::Image::default()),
+                display_name: RefCell::new(None),
+                icon: RefCell::new(None),
+                progress: Cell::new(1.0),
+                cancellable: RefCell::new(None),
+                monitor: RefCell::new(None),
+                thumbnail_job: RefCell::new(None),
+                bound: Cell::new(false),
+                loaded: Cell::new(false),
+                animation: RefCell::new(None),
             }
         }
     }
@@ -136,5 +233,12 @@ mod imp {
 
     // Trait shared by all GObjects
     #[glib_macros::derived_properties]
-    impl ObjectImpl for FileObject {}
+    impl ObjectImpl for FileObject {
+        // files removed from the list don't need their thumbnail anymore
+        fn dispose(&self) {
+            if let Some(job) = self.thumbnail_job.take() {
+                job.cancel();
+            }
+        }
+    }
 }
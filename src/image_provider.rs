@@ -0,0 +1,83 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use gtk::gdk::subclass::prelude::*;
+use gtk::gdk::{self, ContentFormats};
+use gtk::gio::{self, File};
+use gtk::glib;
+use gtk::prelude::*;
+
+glib::wrapper! {
+    /// Offers a local image as the picture itself, for targets that want it rather than the file.
+    /// The image is only read when a target asks for it, so starting a drag doesn't wait
+    /// for a large photo to be loaded.
+    pub struct ImageProvider(ObjectSubclass<imp::ImageProvider>)
+        @extends gdk::ContentProvider;
+}
+
+impl ImageProvider {
+    /// Returns None when the file is not a local image.
+    /// The type is guessed from the name, so the file isn't touched.
+    pub fn new(file: &File) -> Option<Self> {
+        let path = file.path()?;
+        let (content_type, _) = gio::content_type_guess(Some(&path), None);
+        let mime_type = gio::content_type_get_mime_type(&content_type)?;
+        if !mime_type.starts_with("image/") {
+            return None;
+        }
+        let provider: Self = glib::Object::new();
+        provider.imp().file.replace(Some(file.clone()));
+        provider.imp().mime_type.replace(mime_type.to_string());
+        Some(provider)
+    }
+}
+
+mod imp {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[derive(Default)]
+    pub struct ImageProvider {
+        pub file: RefCell<Option<File>>,
+        pub mime_type: RefCell<String>,
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for ImageProvider {
+        const NAME: &'static str = "RipDragImageProvider";
+        type Type = super::ImageProvider;
+        type ParentType = gdk::ContentProvider;
+    }
+
+    impl ObjectImpl for ImageProvider {}
+
+    impl ContentProviderImpl for ImageProvider {
+        fn formats(&self) -> ContentFormats {
+            ContentFormats::new(&[self.mime_type.borrow().as_str()])
+        }
+
+        fn write_mime_type_future(
+            &self,
+            _mime_type: &str,
+            stream: &gio::OutputStream,
+            io_priority: glib::Priority,
+        ) -> Pin<Box<dyn Future<Output = Result<(), glib::Error>> + 'static>> {
+            let file = self.file.borrow().clone();
+            let stream = stream.clone();
+            Box::pin(async move {
+                let Some(file) = file else {
+                    return Err(glib::Error::new(gio::IOErrorEnum::NotFound, "No image"));
+                };
+                let (bytes, _) = file.load_bytes_future().await.inspect_err(|err| {
+                    eprintln!("{}: {}", file.parse_name(), err);
+                })?;
+                stream
+                    .write_all_future(bytes, io_priority)
+                    .await
+                    .map_err(|(_, err)| err)?;
+                Ok(())
+            })
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::ffi::OsStr;
+use std::io::BufRead;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use clap::ValueEnum;
+use gtk::gio;
+use serde::Deserialize;
+
+use crate::command::Command;
+use crate::ARGS;
+
+/// How paths are read from stdin with --from-stdin.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InputFormat {
+    /// One path per line
+    #[default]
+    Path,
+    /// NUL-delimited paths, like the output of `find -print0`
+    Null,
+    /// One JSON object per line
+    Json,
+}
+
+pub fn input_format() -> InputFormat {
+    let args = ARGS.get().unwrap();
+    if args.null {
+        InputFormat::Null
+    } else {
+        args.input_format
+    }
+}
+
+/// A line of JSON input, e.g. `{"uri": "file:///tmp/a.txt", "name": "A", "icon": "text-x-generic"}`.
+#[derive(Deserialize, Debug)]
+struct Entry {
+    uri: Option<String>,
+    path: Option<PathBuf>,
+    /// Shown instead of the path
+    name: Option<String>,
+    /// Icon name or path to an image, shown instead of the thumbnail
+    icon: Option<String>,
+}
+
+impl TryFrom<Entry> for Command {
+    type Error = String;
+
+    fn try_from(entry: Entry) -> Result<Self, Self::Error> {
+        let file = match (entry.uri, entry.path) {
+            (Some(uri), None) => gio::File::for_uri(&uri),
+            (None, Some(path)) => gio::File::for_path(path),
+            _ => return Err("Entries need either a uri or a path".to_string()),
+        };
+        Ok(Command::Add {
+            file,
+            name: entry.name,
+            icon: entry.icon,
+        })
+    }
+}
+
+/// Splits stdin into records, lines or NUL-terminated entries depending on the format.
+pub fn records(stdin: impl BufRead) -> impl Iterator<Item = Vec<u8>> {
+    let delimiter = match input_format() {
+        InputFormat::Null => b'\0',
+        _ => b'\n',
+    };
+    stdin
+        .split(delimiter)
+        .map_while(Result::ok)
+        .map(move |mut record| {
+            if delimiter == b'\n' && record.ends_with(b"\r") {
+                record.pop();
+            }
+            record
+        })
+        .filter(|record| !record.is_empty())
+}
+
+/// Turns a record read from stdin into a command.
+/// Paths are taken as they are, so they may contain newlines with --null. URIs are accepted too.
+pub fn parse_record(record: &[u8]) -> Result<Command, String> {
+    match input_format() {
+        InputFormat::Json => {
+            let entry: Entry = serde_json::from_slice(record)
+                .map_err(|err| format!("{}: {}", String::from_utf8_lossy(record), err))?;
+            Command::try_from(entry)
+        }
+        _ if ARGS.get().unwrap().stdin_commands => std::str::from_utf8(record)
+            .map_err(|err| err.to_string())?
+            .parse(),
+        _ => Ok(Command::Add {
+            file: gio::File::for_commandline_arg(OsStr::from_bytes(record)),
+            name: None,
+            icon: None,
+        }),
+    }
+}
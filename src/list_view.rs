@@ -3,37 +3,44 @@ use gtk::gdk::*;
 use gtk::gio::{self, ListStore};
 use gtk::prelude::*;
 use gtk::{
-    gdk, CenterBox, DragSource, Label, ListItem, ListView, MultiSelection, SignalListItemFactory,
-    Widget,
+    gdk, CenterBox, DragSource, GridView, Label, ListItem, ListView, MultiSelection,
+    SignalListItemFactory, Widget,
 };
 
 use crate::file_object::FileObject;
 use crate::util::{
-    drag_source_and_exit, generate_content_provider, generate_file_model, setup_drag_source_all,
+    generate_content_provider, generate_file_model, setup_drag_end, setup_drag_source_all,
     ListWidget,
 };
 use crate::{ARGS, CURRENT_DIRECTORY};
 
+/// The grid never has more columns than this, however wide the window is.
+const MAX_COLUMNS: u32 = 64;
+
+/// Height of the drag all button in the grid, where the icons are too large to size it.
+const GRID_HEADER_HEIGHT: i32 = 32;
+
 pub fn generate_list_view() -> ListWidget {
     let list_data = build_list_data();
     setup_factory(&list_data.1, &list_data.0);
-    let list_view = ListView::new(Some(list_data.0), Some(list_data.1));
-
-    // lol
-    let model = list_view
-        .model()
-        .unwrap()
-        .downcast::<MultiSelection>()
-        .unwrap()
-        .model()
-        .unwrap()
-        .downcast::<ListStore>()
-        .unwrap();
-    let widget = list_view.upcast::<Widget>();
+    let selection = list_data.0.clone();
+    let widget = if ARGS.get().unwrap().grid {
+        // as many columns as fit the width of the window
+        GridView::builder()
+            .model(&list_data.0)
+            .factory(&list_data.1)
+            .max_columns(MAX_COLUMNS)
+            .build()
+            .upcast::<Widget>()
+    } else {
+        ListView::new(Some(list_data.0), Some(list_data.1)).upcast::<Widget>()
+    };
+    let model = selection.model().unwrap().downcast::<ListStore>().unwrap();
 
     ListWidget {
         list_model: model,
         widget,
+        selection: Some(selection),
     }
 }
 
@@ -55,7 +62,7 @@ fn create_drag_source(row: &CenterBox, selection: &MultiSelection) -> DragSource
             // This will prevent the click to trigger, a drag should happen!
             me.set_state(gtk::EventSequenceState::Claimed);
             let selected = selection.selection();
-            let mut files: Vec<String> = Vec::with_capacity(selected.size() as usize);
+            let mut files: Vec<gio::File> = Vec::with_capacity(selected.size() as usize);
             for index in 0..selected.size() {
                 files.push(
                     selection
@@ -63,15 +70,13 @@ fn create_drag_source(row: &CenterBox, selection: &MultiSelection) -> DragSource
                         .unwrap()
                         .downcast::<FileObject>()
                         .unwrap()
-                        .file()
-                        .uri()
-                        .to_string(),
+                        .file(),
                 );
             }
 
             // Is the activated row also selected?
-            let row_file = get_file(&row).uri().to_string();
-            if !files.contains(&row_file) {
+            let row_file = get_file(&row);
+            if !files.iter().any(|file| file.equal(&row_file)) {
                 selection.unselect_all();
                 generate_content_provider(&[row_file])
             } else {
@@ -80,9 +85,10 @@ fn create_drag_source(row: &CenterBox, selection: &MultiSelection) -> DragSource
         }
     ));
 
-    if ARGS.get().unwrap().and_exit {
-        drag_source_and_exit(&drag_source);
-    }
+    setup_drag_end(
+        &drag_source,
+        &selection.model().and_downcast::<ListStore>().unwrap(),
+    );
     drag_source
 }
 
@@ -108,6 +114,10 @@ fn create_gesture_click(row: &CenterBox) -> gtk::GestureClick {
                     eprint!("{}", err);
                     err
                 });
+            } else if let Err(err) =
+                gio::AppInfo::launch_default_for_uri(&file.uri(), gio::AppLaunchContext::NONE)
+            {
+                eprintln!("{}", err);
             }
         }
     ));
@@ -122,8 +132,8 @@ fn get_file(row: &CenterBox) -> gio::File {
     } else {
         row.center_widget().unwrap()
     };
-    // This is safe because the tooltip is always set to the full path
-    gio::File::for_path(file_widget.tooltip_text().unwrap())
+    // This is safe because the tooltip is always set to the full path or URI
+    gio::File::for_parse_name(&file_widget.tooltip_text().unwrap())
 }
 
 // Setup the widgets in the ListView
@@ -133,6 +143,10 @@ fn setup_factory(factory: &SignalListItemFactory, list: &MultiSelection) {
         list,
         move |_, list_item| {
             let row = CenterBox::default();
+            // in the grid the name is below the thumbnail
+            if ARGS.get().unwrap().grid {
+                row.set_orientation(gtk::Orientation::Vertical);
+            }
 
             let drag_source = create_drag_source(&row, &list);
             if !ARGS.get().unwrap().no_click {
@@ -148,6 +162,17 @@ fn setup_factory(factory: &SignalListItemFactory, list: &MultiSelection) {
         }
     ));
 
+    factory.connect_unbind(|_, list_item| {
+        if let Some(file_object) = list_item
+            .downcast_ref::<ListItem>()
+            .expect("Needs to be ListItem")
+            .item()
+            .and_downcast::<FileObject>()
+        {
+            file_object.unbind();
+        }
+    });
+
     factory.connect_bind(|_, list_item| {
         let file_object = list_item
             .downcast_ref::<ListItem>()
@@ -168,7 +193,9 @@ fn setup_factory(factory: &SignalListItemFactory, list: &MultiSelection) {
 
         // show either relative or absolute path
         // only used for the display label
-        let str = if ARGS.get().unwrap().basename
+        let str = if let Some(display_name) = file_object.display_name() {
+            display_name
+        } else if ARGS.get().unwrap().basename
             || file_object
                 .file()
                 .has_parent(Some(CURRENT_DIRECTORY.get().unwrap()))
@@ -192,6 +219,8 @@ fn setup_factory(factory: &SignalListItemFactory, list: &MultiSelection) {
             .ellipsize(gtk::pango::EllipsizeMode::End)
             .tooltip_text(&path);
 
+        file_object.bind();
+
         if ARGS.get().unwrap().icons_only {
             file_row.set_start_widget(Some(&label.visible(false).build()));
             file_row.set_center_widget(Some(&file_object.thumbnail()))
@@ -199,14 +228,62 @@ fn setup_factory(factory: &SignalListItemFactory, list: &MultiSelection) {
             file_row.set_center_widget(Some(&label.build()));
             file_row.set_start_widget(Some(&file_object.thumbnail()))
         }
+
+        if file_object.cancellable().is_some() {
+            file_row.set_end_widget(Some(&create_progress_box(&file_object)));
+        } else {
+            file_row.set_end_widget(None::<&Widget>);
+        }
     });
 }
 
+/// Shows how much of a file has been downloaded and a button to cancel it.
+/// Hides itself once the download is done.
+fn create_progress_box(file_object: &FileObject) -> gtk::Box {
+    let progress_box = gtk::Box::new(gtk::Orientation::Horizontal, 4);
+    let progress_bar = gtk::ProgressBar::builder()
+        .valign(gtk::Align::Center)
+        .build();
+    let cancel_button = gtk::Button::builder()
+        .icon_name("process-stop-symbolic")
+        .tooltip_text("Cancel download")
+        .has_frame(false)
+        .build();
+    cancel_button.connect_clicked(clone!(
+        #[weak]
+        file_object,
+        move |_| {
+            if let Some(cancellable) = file_object.cancellable() {
+                cancellable.cancel();
+            }
+        }
+    ));
+
+    file_object
+        .bind_property("progress", &progress_bar, "fraction")
+        .sync_create()
+        .build();
+    file_object
+        .bind_property("cancellable", &progress_box, "visible")
+        .transform_to(|_, cancellable: Option<gio::Cancellable>| Some(cancellable.is_some()))
+        .sync_create()
+        .build();
+
+    progress_box.append(&progress_bar);
+    progress_box.append(&cancel_button);
+    progress_box
+}
+
 /// Creates an outer box that adds a drag all button to the top
 pub fn create_outer_box(list: &ListWidget) -> Widget {
     let outer_box = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    let args = ARGS.get().unwrap();
     let row = gtk::CenterBox::builder()
-        .height_request(ARGS.get().unwrap().icon_size)
+        .height_request(if args.grid {
+            GRID_HEADER_HEIGHT
+        } else {
+            args.icon_size
+        })
         .focusable(true)
         .build();
     let label = Label::builder()
@@ -219,13 +296,11 @@ pub fn create_outer_box(list: &ListWidget) -> Widget {
 
     let drag_source = DragSource::new();
     setup_drag_source_all(&drag_source, &list.list_model);
-    if !ARGS.get().unwrap().no_click {
+    if !args.no_click {
         let gesture_click = create_gesture_click(&row);
         row.add_controller(gesture_click);
     }
-    if ARGS.get().unwrap().and_exit {
-        drag_source_and_exit(&drag_source);
-    }
+    setup_drag_end(&drag_source, &list.list_model);
 
     // Add styling
     let provider = gtk::CssProvider::new();
@@ -239,6 +314,5 @@ pub fn create_outer_box(list: &ListWidget) -> Widget {
     row.add_controller(drag_source);
     outer_box.append(&row);
     outer_box.append(&list.widget);
-    let outer_box = outer_box.upcast::<Widget>();
-    outer_box
+    outer_box.upcast::<Widget>()
 }
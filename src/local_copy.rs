@@ -0,0 +1,118 @@
+use std::future::Future;
+use std::pin::Pin;
+
+use gtk::gdk::subclass::prelude::*;
+use gtk::gdk::{self, ContentFormats};
+use gtk::gio::{self, File};
+use gtk::glib;
+use gtk::prelude::*;
+
+use crate::payload::TEXT_MIME_TYPES;
+use crate::util::tmp_location;
+
+glib::wrapper! {
+    /// Offers the files as plain text paths. Files without a local path, like `https://` URIs,
+    /// are only downloaded when a target asks for the text, so that targets taking the URIs
+    /// directly don't wait for a copy they don't need.
+    pub struct LocalPathsProvider(ObjectSubclass<imp::LocalPathsProvider>)
+        @extends gdk::ContentProvider;
+}
+
+impl LocalPathsProvider {
+    pub fn new(files: &[File]) -> Self {
+        let provider: Self = glib::Object::new();
+        provider.imp().files.replace(files.to_vec());
+        provider
+    }
+}
+
+/// Copies a remote file to a temporary location.
+pub async fn download(file: &File) -> Result<File, glib::Error> {
+    let name = file
+        .basename()
+        .and_then(|name| name.to_str().map(str::to_string))
+        .filter(|name| !name.is_empty() && name != "/")
+        .unwrap_or_else(|| "download".to_string());
+    let tmp_file = tmp_location(&name).ok_or_else(|| {
+        glib::Error::new(
+            gio::IOErrorEnum::Failed,
+            "Could not create a temporary directory",
+        )
+    })?;
+    let (copy, _) = file.copy_future(&tmp_file, gio::FileCopyFlags::NONE, glib::Priority::DEFAULT);
+    copy.await?;
+    Ok(tmp_file)
+}
+
+mod imp {
+    use std::cell::RefCell;
+
+    use super::*;
+
+    #[derive(Default)]
+    pub struct LocalPathsProvider {
+        pub files: RefCell<Vec<File>>,
+        /// The paths once the files have been downloaded, so every format uses the same copies
+        paths: RefCell<Option<String>>,
+    }
+
+    impl LocalPathsProvider {
+        async fn local_paths(&self) -> Result<String, glib::Error> {
+            if let Some(paths) = self.paths.borrow().clone() {
+                return Ok(paths);
+            }
+            let files = self.files.borrow().clone();
+            let mut paths = Vec::with_capacity(files.len());
+            for file in files {
+                let path = match file.path() {
+                    Some(path) => path,
+                    None => download(&file)
+                        .await
+                        .map_err(|err| {
+                            eprintln!("{}: {}", file.parse_name(), err);
+                            err
+                        })?
+                        .path()
+                        .unwrap_or_default(),
+                };
+                paths.push(path.to_string_lossy().into_owned());
+            }
+            let paths = paths.join("\n");
+            self.paths.replace(Some(paths.clone()));
+            Ok(paths)
+        }
+    }
+
+    #[glib::object_subclass]
+    impl ObjectSubclass for LocalPathsProvider {
+        const NAME: &'static str = "RipDragLocalPathsProvider";
+        type Type = super::LocalPathsProvider;
+        type ParentType = gdk::ContentProvider;
+    }
+
+    impl ObjectImpl for LocalPathsProvider {}
+
+    impl ContentProviderImpl for LocalPathsProvider {
+        fn formats(&self) -> ContentFormats {
+            ContentFormats::new(TEXT_MIME_TYPES)
+        }
+
+        fn write_mime_type_future(
+            &self,
+            _mime_type: &str,
+            stream: &gio::OutputStream,
+            io_priority: glib::Priority,
+        ) -> Pin<Box<dyn Future<Output = Result<(), glib::Error>> + 'static>> {
+            let obj = self.obj().clone();
+            let stream = stream.clone();
+            Box::pin(async move {
+                let paths = obj.imp().local_paths().await?;
+                stream
+                    .write_all_future(paths.into_bytes(), io_priority)
+                    .await
+                    .map_err(|(_, err)| err)?;
+                Ok(())
+            })
+        }
+    }
+}
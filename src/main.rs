@@ -1,21 +1,49 @@
-use std::io::{self, BufRead, Write};
+use std::cell::OnceCell;
+use std::ffi::CString;
+use std::io;
 use std::path::PathBuf;
+use std::rc::Rc;
 use std::sync::OnceLock;
 
-use clap::Parser;
+use clap::builder::ArgPredicate;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use command::{run_commands, Command};
 use compact_view::generate_compact_view;
 use file_object::FileObject;
 use gtk::gio::{ApplicationFlags, ListStore};
 use gtk::glib::{self, clone, set_program_name, Propagation};
 use gtk::prelude::*;
-use gtk::{gio, Application, ApplicationWindow, EventControllerKey, PolicyType, ScrolledWindow};
+use gtk::{
+    gio, Application, ApplicationWindow, EventControllerKey, MultiSelection, PolicyType,
+    ScrolledWindow,
+};
+use input::{input_format, parse_record, records, InputFormat};
 use list_view::{create_outer_box, generate_list_view};
+use output::OutputFormat;
+use transfer::ConflictPolicy;
 use util::setup_drop_target;
 
+mod animation;
+mod command;
 mod compact_view;
+mod dbus;
+mod expand;
 mod file_object;
+mod image_provider;
+mod input;
 mod list_view;
+mod local_copy;
+mod monitor;
+mod output;
+mod payload;
+mod scheduler;
+mod socket;
+mod thumbnailer;
+mod thumbnails;
+mod transfer;
 mod util;
+mod watch;
 
 #[derive(Parser, Clone, Debug)]
 #[command(about, version)]
@@ -28,15 +56,53 @@ struct Cli {
     #[arg(short, long)]
     target: bool,
 
+    /// Accept drops that are added to the files to drag, skipping files already in the list
+    #[arg(short = 'S', long, conflicts_with = "target", group = "keeps_drops")]
+    shelf: bool,
+
     /// With --target, keep files to drag out
-    #[arg(short, long, requires = "target")]
+    #[arg(short, long, requires = "target", group = "keeps_drops")]
     keep: bool,
 
+    /// With --keep or --shelf, don't download remote files bigger than MIB mebibytes
+    #[arg(long, value_name = "MIB", requires = "keeps_drops")]
+    download_limit: Option<u64>,
+
+    /// How dragged and dropped files are printed, one path per line by default
+    #[arg(short, long, value_name = "FORMAT", value_enum)]
+    output_format: Option<OutputFormat>,
+
+    /// Print NUL-delimited paths, same as --output-format null
+    #[arg(short = '0', long, conflicts_with = "output_format")]
+    print0: bool,
+
+    /// With --target, copy, move or link dropped files into DIR
+    #[arg(long, value_name = "DIR", requires = "target")]
+    dest: Option<PathBuf>,
+
+    /// With --dest, what to do when a dropped file already exists
+    #[arg(
+        long,
+        value_name = "POLICY",
+        value_enum,
+        requires = "dest",
+        default_value_t = ConflictPolicy::Rename
+    )]
+    on_conflict: ConflictPolicy,
+
+    /// Offer to move files, moved files are removed from the list
+    #[arg(short, long = "move")]
+    move_files: bool,
+
+    /// With --move, trash files that the target only copied
+    #[arg(short = 'T', long, requires = "move_files")]
+    trash: bool,
+
     /// Make the window resizable
     #[arg(short, long)]
     resizable: bool,
 
-    /// Exit after first successful drag or drop
+    /// Exit after the first drag or drop, with status 1 if the drag was cancelled
     #[arg(short = 'x', long)]
     and_exit: bool,
 
@@ -44,12 +110,30 @@ struct Cli {
     #[arg(short, long)]
     icons_only: bool,
 
-    /// Don't load thumbnails from images
+    /// Don't load thumbnails
     #[arg(short, long)]
     disable_thumbnails: bool,
 
-    /// Size of icons and thumbnails
-    #[arg(short = 's', long, value_name = "SIZE", default_value_t = 32)]
+    /// Play animated images (GIF, APNG, WebP) while they are shown
+    #[arg(long, conflicts_with = "disable_thumbnails")]
+    animate: bool,
+
+    /// Save new thumbnails to the shared thumbnail cache
+    #[arg(long, conflicts_with = "disable_thumbnails")]
+    write_thumbnails: bool,
+
+    /// Run the installed thumbnailers (video, PDF, ...) for files without a cached thumbnail
+    #[arg(long, conflicts_with = "disable_thumbnails")]
+    thumbnailers: bool,
+
+    /// Size of icons and thumbnails, 128 with --grid
+    #[arg(
+        short = 's',
+        long,
+        value_name = "SIZE",
+        default_value_t = 32,
+        default_value_if("grid", ArgPredicate::IsPresent, "128")
+    )]
     icon_size: i32,
 
     /// Min width of the main window
@@ -60,10 +144,95 @@ struct Cli {
     #[arg(short = 'H', long, value_name = "HEIGHT", default_value_t = 360)]
     content_height: i32,
 
+    /// Add the files inside directories instead of the directories themselves
+    #[arg(short = 'R', long)]
+    recursive: bool,
+
+    /// With --recursive, descend at most DEPTH directories, 1 only adds the files directly inside
+    #[arg(
+        long,
+        value_name = "DEPTH",
+        requires = "recursive",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    max_depth: Option<u32>,
+
+    /// With --recursive, include hidden files
+    #[arg(long, requires = "recursive")]
+    hidden: bool,
+
+    /// With --recursive, don't skip files listed in .gitignore
+    #[arg(long, requires = "recursive")]
+    no_ignore: bool,
+
+    /// Only add files matching GLOB, globs without a slash match the file name
+    #[arg(long, value_name = "GLOB")]
+    include: Vec<String>,
+
+    /// Don't add files matching GLOB, nor descend into matching directories
+    #[arg(long, value_name = "GLOB")]
+    exclude: Vec<String>,
+
+    /// Show the files of DIR, updated as they are added or removed
+    #[arg(short = 'w', long, value_name = "DIR", conflicts_with_all = ["paths", "recursive"])]
+    watch: Option<PathBuf>,
+
+    /// With --watch, show only the N newest files
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "watch",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    newest: Option<u32>,
+
     /// Accept paths from stdin
     #[arg(short = 'I', long)]
     from_stdin: bool,
 
+    /// How paths are read with --from-stdin
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        value_enum,
+        default_value_t = InputFormat::Path,
+        requires = "from_stdin"
+    )]
+    input_format: InputFormat,
+
+    /// Read NUL-delimited paths, same as --input-format null
+    #[arg(
+        short = 'z',
+        long,
+        requires = "from_stdin",
+        conflicts_with = "input_format"
+    )]
+    null: bool,
+
+    /// With --from-stdin, read commands instead of paths
+    #[arg(short = 'C', long, requires = "from_stdin")]
+    stdin_commands: bool,
+
+    /// Listen for commands on a control socket called NAME
+    #[arg(long, value_name = "NAME", value_parser = socket::parse_name)]
+    socket: Option<String>,
+
+    /// Add the PATHs to the window listening on NAME, or open one that does
+    #[arg(long, value_name = "NAME", value_parser = socket::parse_name, conflicts_with = "socket")]
+    send: Option<String>,
+
+    /// Add PATHs to the window that is already open instead of opening another one
+    #[arg(short, long)]
+    unique: bool,
+
+    /// Like --unique, but only share the window with invocations using the same NAME
+    #[arg(long, value_name = "NAME", conflicts_with = "unique")]
+    instance: Option<String>,
+
+    /// Export a D-Bus interface to control the window
+    #[arg(long)]
+    dbus: bool,
+
     /// Show a drag all button
     #[arg(short = 'a', long)]
     all: bool,
@@ -72,11 +241,15 @@ struct Cli {
     #[arg(short = 'A', long)]
     all_compact: bool,
 
+    /// Show the files in a grid, with their names below large thumbnails
+    #[arg(short = 'g', long, conflicts_with = "all_compact")]
+    grid: bool,
+
     /// Don't open files on click
     #[arg(short = 'n', long)]
     no_click: bool,
 
-    /// Paths to the files you want to drag
+    /// Paths or URIs of the files you want to drag
     #[arg(value_name = "PATH")]
     paths: Vec<PathBuf>,
 
@@ -91,29 +264,152 @@ static CURRENT_DIRECTORY: OnceLock<gio::File> = OnceLock::new();
 /// Constant that is initialized once on start. clap will parse the commandline arguments into this.
 static ARGS: OnceLock<Cli> = OnceLock::new();
 
-fn main() {
+fn main() -> glib::ExitCode {
     CURRENT_DIRECTORY
         .set(gio::File::for_path("."))
         .expect("Could not set CURRENT_DIRECTORY");
     let args = Cli::parse();
     ARGS.set(args).expect("Could not set ARGS");
+    if ARGS.get().unwrap().stdin_commands && input_format() == InputFormat::Json {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "--stdin-commands can't be used with --input-format json",
+            )
+            .exit();
+    }
+    check_paths();
+    if let Some(name) = &ARGS.get().unwrap().send {
+        if socket::send(name, &ARGS.get().unwrap().paths).is_ok() {
+            std::process::exit(0);
+        }
+    }
     set_program_name(Some("ripdrag"));
     let app = Application::builder()
-        .application_id("it.catboy.ripdrag")
-        .flags(ApplicationFlags::NON_UNIQUE)
+        .application_id(application_id())
+        .flags(application_flags())
         .build();
-    app.connect_activate(build_ui);
-    app.run_with_args(&[""]); // we don't want gtk to parse the arguments. cleaner solutions are welcome
+
+    let model: Rc<OnceCell<ListStore>> = Rc::default();
+    app.connect_command_line(move |app, command_line| match model.get() {
+        // another invocation with --unique or --instance
+        Some(model) => add_forwarded_paths(model, command_line),
+        None => {
+            let _ = model.set(build_ui(app));
+            glib::ExitCode::SUCCESS
+        }
+    });
+    // GApplication leaves the arguments alone because it handles the command line
+    // and has no options of its own. They are parsed by clap in the instance with the window.
+    let args: Vec<String> = std::env::args_os()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    app.run_with_args(&args)
 }
 
-fn build_ui(app: &Application) {
-    // Parse arguments and check if files exist
+/// Instances with the same application id share one window with --unique or --instance.
+fn application_id() -> String {
+    match &ARGS.get().unwrap().instance {
+        // every element of an application id has to be a valid identifier
+        Some(name) => {
+            let name: String = name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            if name.starts_with(|c: char| c.is_ascii_digit()) {
+                format!("it.catboy.ripdrag._{}", name)
+            } else {
+                format!("it.catboy.ripdrag.{}", name)
+            }
+        }
+        None => "it.catboy.ripdrag".to_string(),
+    }
+}
+
+fn application_flags() -> ApplicationFlags {
+    let args = ARGS.get().unwrap();
+    if args.unique || args.instance.is_some() {
+        ApplicationFlags::HANDLES_COMMAND_LINE
+    } else {
+        ApplicationFlags::HANDLES_COMMAND_LINE | ApplicationFlags::NON_UNIQUE
+    }
+}
+
+/// Prints the message on the stderr of the invocation that forwarded its command line.
+fn printerr(command_line: &gio::ApplicationCommandLine, message: &str) {
+    use glib::translate::ToGlibPtr;
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    // the message is passed as an argument, so `%` in it is printed as is
+    unsafe {
+        gio::ffi::g_application_command_line_printerr(
+            command_line.to_glib_none().0,
+            c"%s\n".as_ptr(),
+            message.as_ptr(),
+        );
+    }
+}
+
+/// Adds the paths of another invocation to the window.
+/// Only its paths are used, the options of the first invocation stay in effect,
+/// including --recursive and the filters. Errors are printed by the other invocation.
+fn add_forwarded_paths(
+    model: &ListStore,
+    command_line: &gio::ApplicationCommandLine,
+) -> glib::ExitCode {
+    let args = match Cli::try_parse_from(command_line.arguments()) {
+        Ok(args) => args,
+        Err(err) => {
+            printerr(command_line, err.to_string().trim_end());
+            return glib::ExitCode::FAILURE;
+        }
+    };
+    let mut files = Vec::with_capacity(args.paths.len());
+    for path in &args.paths {
+        // relative paths are resolved against the directory of the other invocation
+        let file = command_line.create_file_for_arg(path);
+        if !file.query_exists(gio::Cancellable::NONE) {
+            printerr(
+                command_line,
+                &format!("{0}: No such file or directory", path.display()),
+            );
+            return glib::ExitCode::FAILURE;
+        }
+        files.push(file);
+    }
+    if expand::is_active() {
+        expand::stream_into(model, files);
+    } else {
+        let files: Vec<FileObject> = files.iter().map(FileObject::new).collect();
+        model.extend_from_slice(&files);
+    }
+    glib::ExitCode::SUCCESS
+}
+
+/// Check if files exist, URIs are checked through gio
+fn check_paths() {
     for path in &ARGS.get().unwrap().paths {
-        if !path.exists() {
+        if !gio::File::for_commandline_arg(path).query_exists(gio::Cancellable::NONE) {
             eprintln!("{0}: No such file or directory", path.display());
             std::process::exit(1);
         }
     }
+    if let Some(dir) = &ARGS.get().unwrap().watch {
+        let dir = gio::File::for_commandline_arg(dir);
+        let file_type = dir.query_file_type(gio::FileQueryInfoFlags::NONE, gio::Cancellable::NONE);
+        if file_type != gio::FileType::Directory {
+            eprintln!("{0}: Not a directory", dir.parse_name());
+            std::process::exit(1);
+        }
+    }
+    if let Some(dest) = &ARGS.get().unwrap().dest {
+        if !dest.is_dir() {
+            eprintln!("{0}: Not a directory", dest.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+fn build_ui(app: &Application) -> ListStore {
     // Create a scrollable list
     let list_data = if ARGS.get().unwrap().all_compact {
         generate_compact_view()
@@ -129,7 +425,7 @@ fn build_ui(app: &Application) {
         list_data.widget
     };
 
-    if ARGS.get().unwrap().target {
+    if ARGS.get().unwrap().target || ARGS.get().unwrap().shelf {
         setup_drop_target(&list_data.list_model, &child);
     }
 
@@ -165,40 +461,75 @@ fn build_ui(app: &Application) {
     });
 
     window.add_controller(event_controller);
+    file_object::set_scale_factor(&list_data.list_model, window.scale_factor());
+    window.connect_scale_factor_notify(clone!(
+        #[weak(rename_to = model)]
+        list_data.list_model,
+        move |window| file_object::set_scale_factor(&model, window.scale_factor())
+    ));
     window.present();
 
-    if ARGS.get().unwrap().from_stdin {
-        listen_to_stdin(&list_data.list_model);
+    let args = ARGS.get().unwrap();
+    if args.dbus {
+        dbus::export(app, &list_data.list_model);
+    }
+
+    if let Some(name) = args.socket.as_ref().or(args.send.as_ref()) {
+        socket::listen(
+            name,
+            &list_data.list_model,
+            list_data.selection.as_ref(),
+            &window,
+        );
+    }
+
+    if args.from_stdin {
+        listen_to_stdin(&list_data.list_model, list_data.selection.as_ref(), &window);
     }
+
+    list_data.list_model
 }
 
 /// Listen to input from stdin.
 /// Parses the input and checks if it is an existing file path.
-/// Valid files will be added to the model.
-fn listen_to_stdin(model: &ListStore) {
+/// Valid files will be added to the model, invalid entries are reported on stderr.
+/// With --stdin-commands every line is a command instead.
+fn listen_to_stdin(
+    model: &ListStore,
+    selection: Option<&MultiSelection>,
+    window: &ApplicationWindow,
+) {
     let (sender, receiver) = async_channel::bounded(1);
     gio::spawn_blocking(move || {
-        let stdin = io::stdin();
-        for path in stdin.lock().lines().flatten() {
-            let file = gio::File::for_path(path);
-            if file.query_exists(gio::Cancellable::NONE) {
-                if let Err(err) = sender.send_blocking(file) {
-                    println!("{}", err);
+        for record in records(io::stdin().lock()) {
+            let command =
+                parse_record(&record).and_then(|command| command.validate().map(|_| command));
+            let send = |command| {
+                if let Err(err) = sender.send_blocking(command) {
+                    eprintln!("{}", err);
                 }
-            } else {
-                println!("{} does not exist!", file.parse_name());
+            };
+            match command {
+                Ok(Command::Add { file, name, icon }) if expand::is_active() => {
+                    // the name and icon only apply to the file itself, not to its children
+                    expand::expand(&file, &mut |found| {
+                        let (name, icon) = if found.equal(&file) {
+                            (name.clone(), icon.clone())
+                        } else {
+                            (None, None)
+                        };
+                        send(Command::Add {
+                            file: found,
+                            name,
+                            icon,
+                        })
+                    });
+                }
+                Ok(command) => send(command),
+                Err(err) => eprintln!("{}", err),
             }
-            let _ = io::stdout().flush();
         }
     });
 
-    glib::spawn_future_local(clone!(
-        #[weak]
-        model,
-        async move {
-            while let Ok(file) = receiver.recv().await {
-                model.append(&FileObject::new(&file));
-            }
-        }
-    ));
+    run_commands(receiver, model, selection, window);
 }
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use gtk::gio::{self, FileMonitorEvent, ListStore};
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+
+use crate::file_object::FileObject;
+
+/// Replaces the object with itself, so the views bind it again and show its new name.
+fn refresh(model: &ListStore, file_object: &FileObject) {
+    if let Some(position) = model.find(file_object) {
+        model.splice(position, 1, std::slice::from_ref(file_object));
+    }
+}
+
+/// Watches a file of the list: deleted files are removed, renamed ones are followed and
+/// thumbnails are loaded again once a file has been modified.
+fn watch(model: &ListStore, file_object: &FileObject) {
+    if file_object.monitor().is_some() {
+        return;
+    }
+    // not every location can be monitored, those files are just not watched
+    let Ok(monitor) = file_object
+        .file()
+        .monitor_file(gio::FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE)
+    else {
+        return;
+    };
+    monitor.connect_changed(clone!(
+        #[weak]
+        model,
+        #[weak]
+        file_object,
+        move |_, file, other_file, event| {
+            match event {
+                FileMonitorEvent::Deleted | FileMonitorEvent::MovedOut => {
+                    if let Some(position) = model.find(&file_object) {
+                        model.remove(position);
+                    }
+                }
+                FileMonitorEvent::Renamed => match other_file {
+                    // another file replaced this one, e.g. an editor saving atomically
+                    Some(other_file) if other_file.equal(&file_object.file()) => {
+                        file_object.reload();
+                    }
+                    Some(other_file) if file.equal(&file_object.file()) => {
+                        file_object.set_file(other_file);
+                        refresh(&model, &file_object);
+                    }
+                    _ => {}
+                },
+                // downloads are still being written, their thumbnail is loaded once they are done
+                FileMonitorEvent::ChangesDoneHint | FileMonitorEvent::Created
+                    if file_object.cancellable().is_none() =>
+                {
+                    file_object.reload();
+                }
+                _ => {}
+            }
+        }
+    ));
+    file_object.set_monitor(Some(monitor));
+}
+
+/// How many monitors are created before the window gets to draw again.
+const BATCH_SIZE: usize = 256;
+
+/// Watches the files in batches, so adding a long list doesn't block the window.
+fn watch_later(model: &ListStore, file_objects: Vec<glib::WeakRef<FileObject>>) {
+    if file_objects.is_empty() {
+        return;
+    }
+    glib::spawn_future_local(clone!(
+        #[weak]
+        model,
+        async move {
+            for batch in file_objects.chunks(BATCH_SIZE) {
+                // files removed in the meantime are gone and don't need a monitor
+                for file_object in batch.iter().filter_map(|file_object| file_object.upgrade()) {
+                    watch(&model, &file_object);
+                }
+                glib::timeout_future(Duration::ZERO).await;
+            }
+        }
+    ));
+}
+
+/// Watches every file added to the model.
+pub fn watch_model(model: &ListStore) {
+    watch_later(
+        model,
+        model
+            .iter::<FileObject>()
+            .flatten()
+            .map(|file_object| file_object.downgrade())
+            .collect(),
+    );
+    model.connect_items_changed(|model, position, _, added| {
+        let file_objects = (position..position + added)
+            .filter_map(|position| model.item(position).and_downcast::<FileObject>())
+            .filter(|file_object| file_object.monitor().is_none())
+            .map(|file_object| file_object.downgrade())
+            .collect();
+        watch_later(model, file_objects);
+    });
+}
@@ -0,0 +1,243 @@
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+
+use clap::ValueEnum;
+use gtk::gdk::DragAction;
+use gtk::gio::{self, File};
+use gtk::glib;
+use gtk::prelude::*;
+use serde::Serialize;
+
+use crate::ARGS;
+
+/// How dropped files are written to stdout.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// One path per line
+    #[default]
+    Path,
+    /// NUL-delimited paths, for `xargs -0`
+    Null,
+    /// One URI per line
+    Uri,
+    /// One JSON object per drop
+    Json,
+}
+
+fn output_format() -> OutputFormat {
+    let args = ARGS.get().unwrap();
+    if args.print0 {
+        OutputFormat::Null
+    } else {
+        args.output_format.unwrap_or_default()
+    }
+}
+
+/// Whether drags out of ripdrag are printed. Plain drags print nothing, scripts ask for
+/// the outcome with --and-exit or by choosing an output format.
+fn prints_drags() -> bool {
+    let args = ARGS.get().unwrap();
+    args.and_exit || args.print0 || args.output_format.is_some()
+}
+
+/// Name of a drag action as it is printed.
+pub fn action_name(action: DragAction) -> &'static str {
+    if action.contains(DragAction::MOVE) {
+        "move"
+    } else if action.contains(DragAction::LINK) {
+        "link"
+    } else if action.contains(DragAction::COPY) {
+        "copy"
+    } else if action.contains(DragAction::ASK) {
+        "ask"
+    } else {
+        "none"
+    }
+}
+
+/// A file as it is printed in the JSON format.
+#[derive(Serialize)]
+struct JsonFile {
+    path: Option<String>,
+    uri: String,
+    content_type: Option<String>,
+    size: Option<i64>,
+    /// What was done to a dropped file, which for --dest may differ from the action of the drop
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<&'static str>,
+}
+
+impl JsonFile {
+    async fn new(file: &File, action: Option<DragAction>) -> Self {
+        let info = file
+            .query_info_future(
+                &format!(
+                    "{},{}",
+                    gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE,
+                    gio::FILE_ATTRIBUTE_STANDARD_SIZE
+                ),
+                gio::FileQueryInfoFlags::NONE,
+                glib::Priority::DEFAULT,
+            )
+            .await
+            .ok();
+        JsonFile {
+            path: file.path().map(|path| path.to_string_lossy().into_owned()),
+            uri: file.uri().to_string(),
+            content_type: info
+                .as_ref()
+                .and_then(|info| info.content_type())
+                .map(|content_type| content_type.to_string()),
+            size: info
+                .as_ref()
+                .filter(|info| info.has_attribute(gio::FILE_ATTRIBUTE_STANDARD_SIZE))
+                .map(|info| info.size()),
+            action: action.map(action_name),
+        }
+    }
+}
+
+/// Queries the files one after another, without blocking the main thread.
+async fn json_files(files: impl IntoIterator<Item = (&File, Option<DragAction>)>) -> Vec<JsonFile> {
+    let mut json_files = vec![];
+    for (file, action) in files {
+        json_files.push(JsonFile::new(file, action).await);
+    }
+    json_files
+}
+
+/// Writes VALUE as one line of JSON.
+fn write_json(stdout: &mut impl Write, value: &impl Serialize) -> io::Result<()> {
+    serde_json::to_writer(&mut *stdout, value)?;
+    writeln!(stdout)
+}
+
+/// Writes one line, or one NUL-terminated entry with --output-format null.
+fn write_entry(stdout: &mut impl Write, entry: &[u8], format: OutputFormat) -> io::Result<()> {
+    stdout.write_all(entry)?;
+    if format == OutputFormat::Null {
+        stdout.write_all(b"\0")
+    } else {
+        stdout.write_all(b"\n")
+    }
+}
+
+/// Writes the files in one of the line based formats.
+fn write_files(stdout: &mut impl Write, files: &[File], format: OutputFormat) -> io::Result<()> {
+    files.iter().try_for_each(|file| match format {
+        OutputFormat::Uri => write_entry(stdout, file.uri().as_bytes(), format),
+        OutputFormat::Null => match file.path() {
+            Some(path) => write_entry(stdout, path.as_os_str().as_bytes(), format),
+            None => write_entry(stdout, file.uri().as_bytes(), format),
+        },
+        _ => write_entry(stdout, file.parse_name().as_bytes(), format),
+    })
+}
+
+/// Prints the dropped files in the format chosen on the command line, each with the action
+/// that was performed on it, as one JSON object per drop.
+pub async fn print_dropped(files: &[(File, DragAction)], action: DragAction) {
+    #[derive(Serialize)]
+    struct Dropped {
+        action: &'static str,
+        files: Vec<JsonFile>,
+    }
+
+    let format = output_format();
+    let dropped = match format {
+        OutputFormat::Json => Some(Dropped {
+            action: action_name(action),
+            files: json_files(files.iter().map(|(file, action)| (file, Some(*action)))).await,
+        }),
+        _ => None,
+    };
+    let mut stdout = io::stdout().lock();
+    let result = match dropped {
+        Some(dropped) => write_json(&mut stdout, &dropped),
+        None => {
+            let files: Vec<File> = files.iter().map(|(file, _)| file.clone()).collect();
+            write_files(&mut stdout, &files, format)
+        }
+    };
+    // NUL-delimited output has no newline to trigger a flush
+    if let Err(err) = result.and_then(|_| stdout.flush()) {
+        eprintln!("{}", err);
+    }
+}
+
+/// How a drag out of ripdrag ended.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DragOutcome {
+    /// The files were dropped with this action
+    Dropped(DragAction),
+    /// There was no target or the user gave up
+    Cancelled,
+    /// The drag could not be completed
+    Failed,
+}
+
+impl DragOutcome {
+    /// Status name used in JSON and D-Bus signals.
+    pub fn name(self) -> &'static str {
+        match self {
+            DragOutcome::Dropped(_) => "dropped",
+            DragOutcome::Cancelled => "cancelled",
+            DragOutcome::Failed => "failed",
+        }
+    }
+
+    /// Exit status used with --and-exit.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            DragOutcome::Dropped(_) => 0,
+            DragOutcome::Cancelled => 1,
+            DragOutcome::Failed => 2,
+        }
+    }
+}
+
+/// Prints how a drag ended.
+/// The line based formats only print the files of a successful drop, the outcome is told
+/// by the exit status with --and-exit, and on stderr with --verbose.
+pub async fn print_dragged(files: &[File], outcome: DragOutcome) {
+    #[derive(Serialize)]
+    struct Dragged {
+        status: &'static str,
+        action: &'static str,
+        files: Vec<JsonFile>,
+    }
+
+    let action = match outcome {
+        DragOutcome::Dropped(action) => action,
+        _ => DragAction::empty(),
+    };
+    if ARGS.get().unwrap().verbose {
+        match outcome {
+            DragOutcome::Dropped(_) => eprintln!("Drag finished: {}", action_name(action)),
+            _ => eprintln!("Drag {}", outcome.name()),
+        }
+    }
+    if !prints_drags() {
+        return;
+    }
+    let format = output_format();
+    let dragged = match format {
+        OutputFormat::Json => Some(Dragged {
+            status: outcome.name(),
+            action: action_name(action),
+            files: json_files(files.iter().map(|file| (file, None))).await,
+        }),
+        _ => None,
+    };
+    let mut stdout = io::stdout().lock();
+    let result = match dragged {
+        Some(dragged) => write_json(&mut stdout, &dragged),
+        None if matches!(outcome, DragOutcome::Dropped(_)) => {
+            write_files(&mut stdout, files, format)
+        }
+        None => Ok(()),
+    };
+    if let Err(err) = result.and_then(|_| stdout.flush()) {
+        eprintln!("{}", err);
+    }
+}
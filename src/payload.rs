@@ -0,0 +1,132 @@
+use gtk::gio::{self, File};
+use gtk::glib::{self, Bytes};
+use gtk::prelude::*;
+
+use crate::util::tmp_location;
+
+/// Extensions for the mime types that are commonly dropped.
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("text/plain", "txt"),
+    ("text/html", "html"),
+    ("text/markdown", "md"),
+    ("text/csv", "csv"),
+    ("image/png", "png"),
+    ("image/jpeg", "jpg"),
+    ("image/gif", "gif"),
+    ("image/webp", "webp"),
+    ("image/bmp", "bmp"),
+    ("image/tiff", "tiff"),
+    ("image/svg+xml", "svg"),
+    ("application/pdf", "pdf"),
+    ("application/json", "json"),
+    ("application/xml", "xml"),
+];
+
+/// Text mime types in order of preference.
+pub const TEXT_MIME_TYPES: &[&str] = &["text/plain;charset=utf-8", "text/plain"];
+
+/// Picks a file extension for a mime type, parameters like the charset are ignored.
+fn extension(mime_type: &str) -> String {
+    let mime_type = mime_type.split(';').next().unwrap_or_default().trim();
+    if let Some((_, extension)) = EXTENSIONS.iter().find(|(mime, _)| *mime == mime_type) {
+        return extension.to_string();
+    }
+    // e.g. image/x-portable-anymap is saved as .x-portable-anymap
+    match mime_type.split_once('/') {
+        Some((_, subtype))
+            if !subtype.is_empty()
+                && subtype
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+        {
+            subtype.to_string()
+        }
+        _ => "bin".to_string(),
+    }
+}
+
+/// Text is named after its first line, everything else after the kind of data.
+fn file_stem(bytes: &Bytes, mime_type: &str) -> String {
+    if mime_type.starts_with("text/plain") {
+        let text = String::from_utf8_lossy(bytes);
+        let stem: String = text
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default()
+            .chars()
+            .map(|c| if c == '/' || c.is_control() { '_' } else { c })
+            .take(32)
+            .collect();
+        let stem = stem.trim();
+        if !stem.is_empty() && !stem.starts_with('.') {
+            return stem.to_string();
+        }
+        "dropped-text".to_string()
+    } else if mime_type.starts_with("image/") {
+        "dropped-image".to_string()
+    } else {
+        "dropped-data".to_string()
+    }
+}
+
+/// Whether GIO can open URIs with this scheme. Text like `TODO:fix` parses as a URI too,
+/// but its scheme is not one of them.
+fn is_supported_scheme(scheme: &str) -> bool {
+    scheme.eq_ignore_ascii_case("file")
+        || gio::Vfs::default()
+            .supported_uri_schemes()
+            .iter()
+            .any(|supported| supported.eq_ignore_ascii_case(scheme))
+}
+
+/// Whether dropped text is actually a list of URIs, which some sources send as plain text.
+pub fn is_uri_list(text: &str) -> bool {
+    let mut lines = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .peekable();
+    lines.peek().is_some()
+        && lines.all(|line| {
+            !line.contains(char::is_whitespace)
+                && glib::Uri::parse(line, glib::UriFlags::NONE)
+                    .is_ok_and(|uri| is_supported_scheme(&uri.scheme()))
+        })
+}
+
+/// Saves a dropped payload to a temporary file, so it can be handled like a dropped file.
+pub async fn save_payload(bytes: Bytes, mime_type: &str) -> Option<File> {
+    let name = format!("{}.{}", file_stem(&bytes, mime_type), extension(mime_type));
+    let file = tmp_location(&name)?;
+    file.replace_contents_future(
+        bytes,
+        None,
+        false,
+        gio::FileCreateFlags::REPLACE_DESTINATION,
+    )
+    .await
+    .map_err(|(_, err)| eprintln!("{}: {}", file.parse_name(), err))
+    .ok()?;
+    Some(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uri_lists() {
+        assert!(is_uri_list("file:///tmp/a.txt\r\nfile:///tmp/b.txt\r\n"));
+        assert!(is_uri_list("# comment\nfile:///tmp/a.txt"));
+        assert!(!is_uri_list(""));
+        assert!(!is_uri_list("file:///tmp/a.txt\nsome text"));
+    }
+
+    #[test]
+    fn text_with_unknown_schemes() {
+        assert!(!is_uri_list("TODO:fix"));
+        assert!(!is_uri_list("localhost:8080"));
+        assert!(!is_uri_list("file:///tmp/a.txt\nTODO:fix"));
+    }
+}
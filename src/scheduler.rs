@@ -0,0 +1,204 @@
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Condvar, Mutex, OnceLock};
+
+use gtk::gdk_pixbuf::{Colorspace, Pixbuf};
+use gtk::glib::Bytes;
+use gtk::prelude::*;
+use gtk::{gdk, gio};
+
+use crate::animation::{self, Frame};
+use crate::thumbnails;
+
+/// Decoded thumbnails are kept until they take this many bytes, 64 MiB.
+const CACHE_SIZE: usize = 64 * 1024 * 1024;
+
+/// The pixels of a thumbnail. Unlike a `Pixbuf` they can be sent between threads.
+#[derive(Clone)]
+pub struct Thumbnail {
+    bytes: Bytes,
+    has_alpha: bool,
+    bits_per_sample: i32,
+    width: i32,
+    height: i32,
+    rowstride: i32,
+}
+
+impl Thumbnail {
+    pub fn new(image: &Pixbuf) -> Self {
+        Thumbnail {
+            bytes: image.read_pixel_bytes(),
+            has_alpha: image.has_alpha(),
+            bits_per_sample: image.bits_per_sample(),
+            width: image.width(),
+            height: image.height(),
+            rowstride: image.rowstride(),
+        }
+    }
+
+    pub fn texture(&self) -> gdk::Texture {
+        let image = Pixbuf::from_bytes(
+            &self.bytes,
+            Colorspace::Rgb,
+            self.has_alpha,
+            self.bits_per_sample,
+            self.width,
+            self.height,
+            self.rowstride,
+        );
+        gdk::Texture::for_pixbuf(&image)
+    }
+}
+
+/// URI, modification time and size, so modified files are decoded again.
+type CacheKey = (String, u64, i32);
+
+/// Forgets the oldest thumbnails once they take more than CACHE_SIZE bytes.
+#[derive(Default)]
+struct Cache {
+    thumbnails: HashMap<CacheKey, Thumbnail>,
+    order: VecDeque<CacheKey>,
+    bytes: usize,
+}
+
+impl Cache {
+    fn insert(&mut self, key: CacheKey, thumbnail: Thumbnail) {
+        self.bytes += thumbnail.bytes.len();
+        match self.thumbnails.insert(key.clone(), thumbnail) {
+            Some(replaced) => self.bytes -= replaced.bytes.len(),
+            None => self.order.push_back(key),
+        }
+        while self.bytes > CACHE_SIZE {
+            let Some(oldest) = self.order.pop_front() else {
+                break;
+            };
+            if let Some(removed) = self.thumbnails.remove(&oldest) {
+                self.bytes -= removed.bytes.len();
+            }
+        }
+    }
+}
+
+struct Job {
+    /// Identifies the job and skips it once the row is gone
+    cancellable: gio::Cancellable,
+    /// Decodes and sends back the result
+    run: Box<dyn FnOnce(&Scheduler) + Send>,
+}
+
+/// Decodes thumbnails and animations on a few worker threads, so a long list doesn't decode
+/// all of its images at once. Visible rows come first because jobs are only queued when a
+/// row is bound, and are cancelled when it is unbound.
+struct Scheduler {
+    queue: Mutex<VecDeque<Job>>,
+    wakeup: Condvar,
+    cache: Mutex<Cache>,
+}
+
+fn scheduler() -> &'static Scheduler {
+    static SCHEDULER: OnceLock<Scheduler> = OnceLock::new();
+    SCHEDULER.get_or_init(|| {
+        let workers = std::thread::available_parallelism().map_or(2, |n| n.get().min(4));
+        for _ in 0..workers {
+            std::thread::spawn(|| scheduler().work());
+        }
+        Scheduler {
+            queue: Mutex::new(VecDeque::new()),
+            wakeup: Condvar::new(),
+            cache: Mutex::default(),
+        }
+    })
+}
+
+impl Scheduler {
+    fn work(&self) {
+        loop {
+            let job = {
+                let queue = self.queue.lock().unwrap();
+                let mut queue = self
+                    .wakeup
+                    .wait_while(queue, |queue| queue.is_empty())
+                    .unwrap();
+                queue.pop_front().unwrap()
+            };
+            if job.cancellable.is_cancelled() {
+                continue;
+            }
+            // workers are not restarted, so a file that makes a decoder panic must not take
+            // one down; the reply is dropped and the row keeps its icon
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| (job.run)(self)));
+        }
+    }
+
+    fn thumbnail(&self, file: &gio::File, size: i32) -> Option<Thumbnail> {
+        if !file.query_exists(gio::Cancellable::NONE) {
+            return None;
+        }
+        // without a modification time there is no telling whether a cached thumbnail is outdated
+        let key = thumbnails::mtime(file).map(|mtime| (file.uri().to_string(), mtime, size));
+        if let Some(thumbnail) = key
+            .as_ref()
+            .and_then(|key| self.cache.lock().unwrap().thumbnails.get(key).cloned())
+        {
+            return Some(thumbnail);
+        }
+        let content_type = file
+            .query_info(
+                gio::FILE_ATTRIBUTE_STANDARD_CONTENT_TYPE,
+                gio::FileQueryInfoFlags::NONE,
+                gio::Cancellable::NONE,
+            )
+            .ok()?
+            .content_type()?;
+        let thumbnail = Thumbnail::new(&thumbnails::load(file, &content_type, size)?);
+        if let Some(key) = key {
+            self.cache.lock().unwrap().insert(key, thumbnail.clone());
+        }
+        Some(thumbnail)
+    }
+}
+
+fn push(cancellable: &gio::Cancellable, run: Box<dyn FnOnce(&Scheduler) + Send>) {
+    let scheduler = scheduler();
+    scheduler.queue.lock().unwrap().push_back(Job {
+        cancellable: cancellable.clone(),
+        run,
+    });
+    scheduler.wakeup.notify_one();
+}
+
+/// Queues a thumbnail for the file, SIZE pixels wide. Cancelling the cancellable drops the
+/// job if it hasn't started yet. The receiver gets None when there is no thumbnail.
+pub fn request(
+    file: &gio::File,
+    cancellable: &gio::Cancellable,
+    size: i32,
+) -> async_channel::Receiver<Option<Thumbnail>> {
+    let (reply, receiver) = async_channel::bounded(1);
+    let file = file.clone();
+    push(
+        cancellable,
+        Box::new(move |scheduler| {
+            let _ = reply.send_blocking(scheduler.thumbnail(&file, size));
+        }),
+    );
+    receiver
+}
+
+/// Queues the frames of an animated image, SIZE pixels wide, like `request`.
+/// The receiver gets None when the image is not animated.
+pub fn request_animation(
+    file: &gio::File,
+    cancellable: &gio::Cancellable,
+    size: i32,
+) -> async_channel::Receiver<Option<Vec<Frame>>> {
+    let (reply, receiver) = async_channel::bounded(1);
+    let file = file.clone();
+    push(
+        cancellable,
+        Box::new(move |_| {
+            let _ = reply.send_blocking(animation::decode(&file, size));
+        }),
+    );
+    receiver
+}
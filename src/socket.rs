@@ -0,0 +1,133 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use gtk::gio::{self, ListStore};
+use gtk::prelude::*;
+use gtk::{glib, ApplicationWindow, MultiSelection};
+
+use crate::command::{run_commands, Command};
+
+/// Sockets live in `$XDG_RUNTIME_DIR/ripdrag/NAME.sock`.
+fn socket_path(name: &str) -> PathBuf {
+    glib::user_runtime_dir()
+        .join("ripdrag")
+        .join(format!("{}.sock", name))
+}
+
+/// Forwards the paths or URIs to the window listening on NAME.
+/// Returns an error when there is no such window.
+pub fn send(name: &str, paths: &[PathBuf]) -> io::Result<()> {
+    let mut stream = UnixStream::connect(socket_path(name))?;
+    for path in paths {
+        // the other window may have been started from another directory, and URIs escape
+        // newlines and bytes that aren't UTF-8
+        let file = gio::File::for_commandline_arg(path);
+        writeln!(stream, "add {}", file.uri())?;
+    }
+    stream.shutdown(std::net::Shutdown::Write)?;
+    // errors are sent back, one per line
+    for line in BufReader::new(stream).lines() {
+        eprintln!("{}", line?);
+    }
+    Ok(())
+}
+
+/// Binds the socket, replacing the file left behind by a window that is gone.
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+    if path.exists() {
+        if UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "Another window is listening on this socket",
+            ));
+        }
+        fs::remove_file(path)?;
+    }
+    UnixListener::bind(path)
+}
+
+/// Reads the commands of one client, answering `list` and reporting errors.
+fn handle_client(stream: UnixStream, sender: &async_channel::Sender<Command>) -> io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let command = match line?.parse::<Command>() {
+            Ok(Command::List(_)) => {
+                let (reply, paths) = async_channel::bounded(1);
+                let _ = sender.send_blocking(Command::List(Some(reply)));
+                for path in paths.recv_blocking().unwrap_or_default() {
+                    writeln!(writer, "{}", path)?;
+                }
+                continue;
+            }
+            Ok(command) => command,
+            Err(err) => {
+                writeln!(writer, "{}", err)?;
+                continue;
+            }
+        };
+        if let Err(err) = command.validate() {
+            writeln!(writer, "{}", err)?;
+        } else if sender.send_blocking(command).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Listens on the control socket called NAME.
+/// Clients send the same commands as --stdin-commands, one per line.
+pub fn listen(
+    name: &str,
+    model: &ListStore,
+    selection: Option<&MultiSelection>,
+    window: &ApplicationWindow,
+) {
+    let path = socket_path(name);
+    let listener = match bind(&path) {
+        Ok(listener) => listener,
+        Err(err) => {
+            eprintln!("{}: {}", path.display(), err);
+            return;
+        }
+    };
+
+    let (sender, receiver) = async_channel::bounded(1);
+    gio::spawn_blocking(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    continue;
+                }
+            };
+            // every client gets its own thread, so an idle one doesn't block the others
+            let sender = sender.clone();
+            std::thread::spawn(move || {
+                if let Err(err) = handle_client(stream, &sender) {
+                    eprintln!("{}", err);
+                }
+            });
+        }
+    });
+
+    run_commands(receiver, model, selection, window);
+}
+
+/// Socket names become file names, so they can't contain slashes.
+pub fn parse_name(name: &str) -> Result<String, String> {
+    if name.is_empty() || name.contains('/') {
+        Err("must be a non-empty name without slashes".to_string())
+    } else {
+        Ok(name.to_string())
+    }
+}
@@ -0,0 +1,150 @@
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use gtk::gdk_pixbuf::Pixbuf;
+use gtk::gio;
+use gtk::glib;
+use gtk::prelude::*;
+
+use crate::transfer::delete_recursive;
+use crate::util::tmp_location;
+
+/// Thumbnailers that take longer than this are killed.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An installed `.thumbnailer` file.
+#[derive(Debug)]
+struct Thumbnailer {
+    /// The command line, `%s`, `%u`, `%i` and `%o` are replaced when it is run
+    exec: Vec<String>,
+    mime_types: Vec<String>,
+}
+
+impl Thumbnailer {
+    fn load(path: &std::path::Path) -> Option<Self> {
+        const GROUP: &str = "Thumbnailer Entry";
+        let key_file = glib::KeyFile::new();
+        key_file
+            .load_from_file(path, glib::KeyFileFlags::NONE)
+            .ok()?;
+        // skip thumbnailers whose program isn't installed
+        if let Ok(try_exec) = key_file.string(GROUP, "TryExec") {
+            glib::find_program_in_path(try_exec.as_str())?;
+        }
+        let exec = key_file.string(GROUP, "Exec").ok()?;
+        let exec = glib::shell_parse_argv(exec.as_str())
+            .ok()?
+            .into_iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        let mime_types = key_file
+            .string_list(GROUP, "MimeType")
+            .ok()?
+            .iter()
+            .map(|mime_type| mime_type.to_string())
+            .collect();
+        Some(Thumbnailer { exec, mime_types })
+    }
+
+    fn handles(&self, content_type: &str) -> bool {
+        self.mime_types.iter().any(|mime_type| {
+            gio::content_type_from_mime_type(mime_type)
+                .is_some_and(|mime_type| gio::content_type_is_a(content_type, &mime_type))
+        })
+    }
+
+    /// The command line for one file, or None when the thumbnailer needs a local path
+    /// that the file doesn't have.
+    fn command(&self, file: &gio::File, size: i32, output: &str) -> Option<Vec<String>> {
+        let path = file.path().map(|path| path.to_string_lossy().into_owned());
+        self.exec
+            .iter()
+            .map(|arg| {
+                let mut expanded = String::with_capacity(arg.len());
+                let mut chars = arg.chars();
+                while let Some(c) = chars.next() {
+                    if c != '%' {
+                        expanded.push(c);
+                        continue;
+                    }
+                    match chars.next() {
+                        Some('s') => expanded.push_str(&size.to_string()),
+                        Some('u') => expanded.push_str(&file.uri()),
+                        Some('i') => expanded.push_str(path.as_deref()?),
+                        Some('o') => expanded.push_str(output),
+                        Some('%') => expanded.push('%'),
+                        _ => {}
+                    }
+                }
+                Some(expanded)
+            })
+            .collect()
+    }
+}
+
+/// Thumbnailers in the data directories, the ones of the user first.
+fn thumbnailers() -> &'static [Thumbnailer] {
+    static THUMBNAILERS: OnceLock<Vec<Thumbnailer>> = OnceLock::new();
+    THUMBNAILERS.get_or_init(|| {
+        std::iter::once(glib::user_data_dir())
+            .chain(glib::system_data_dirs())
+            .filter_map(|dir| std::fs::read_dir(dir.join("thumbnailers")).ok())
+            .flat_map(|entries| entries.flatten())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "thumbnailer")
+            })
+            .filter_map(|path| Thumbnailer::load(&path))
+            .collect()
+    })
+}
+
+/// Runs the command, killing it after TIMEOUT. Only the thumbnail workers run thumbnailers,
+/// so there are never more of them at once than there are workers.
+fn run(command: &[String]) -> Result<(), String> {
+    let (program, args) = command.split_first().ok_or("Empty Exec line")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| format!("{}: {}", program, err))?;
+    let start = Instant::now();
+    loop {
+        match child.try_wait().map_err(|err| err.to_string())? {
+            Some(status) if status.success() => return Ok(()),
+            Some(status) => return Err(format!("{}: {}", program, status)),
+            None if start.elapsed() > TIMEOUT => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("{}: Timed out", program));
+            }
+            None => sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+/// Generates a thumbnail of SIZE pixels with the first installed thumbnailer that handles
+/// the content type. Blocking.
+pub fn generate(file: &gio::File, content_type: &str, size: i32) -> Option<Pixbuf> {
+    let thumbnailer = thumbnailers()
+        .iter()
+        .find(|thumbnailer| thumbnailer.handles(content_type))?;
+    let output = tmp_location("thumbnail.png")?;
+    let output_path = output.path()?;
+    let image = thumbnailer
+        .command(file, size, &output_path.to_string_lossy())
+        .map(|command| {
+            run(&command)
+                .and_then(|_| Pixbuf::from_file(&output_path).map_err(|err| err.to_string()))
+                .map_err(|err| eprintln!("{}: {}", file.parse_name(), err))
+                .ok()
+        });
+    // the temporary directory only holds the thumbnail
+    let _ = delete_recursive(&output.parent().unwrap_or(output));
+    image.flatten()
+}
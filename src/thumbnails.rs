@@ -0,0 +1,171 @@
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+use gtk::gdk_pixbuf::{InterpType, Pixbuf};
+use gtk::gio;
+use gtk::glib::{self, ChecksumType};
+use gtk::prelude::*;
+
+use crate::thumbnailer;
+use crate::ARGS;
+
+/// The sizes of the freedesktop thumbnail cache, thumbnails fit in a square of this size.
+const CACHE_SIZES: &[(&str, i32)] = &[
+    ("normal", 128),
+    ("large", 256),
+    ("x-large", 512),
+    ("xx-large", 1024),
+];
+
+fn cache_dir() -> PathBuf {
+    glib::user_cache_dir().join("thumbnails")
+}
+
+/// The smallest cache size that is at least SIZE.
+fn cache_size(size: i32) -> (&'static str, i32) {
+    CACHE_SIZES
+        .iter()
+        .copied()
+        .find(|(_, cache_size)| *cache_size >= size)
+        .unwrap_or(CACHE_SIZES[CACHE_SIZES.len() - 1])
+}
+
+/// Thumbnails are named after the MD5 of the URI.
+fn cache_name(uri: &str) -> String {
+    format!(
+        "{}.png",
+        glib::compute_checksum_for_string(ChecksumType::Md5, uri).unwrap_or_default()
+    )
+}
+
+/// Modification time in seconds, which the cache uses to know if a thumbnail is outdated.
+pub fn mtime(file: &gio::File) -> Option<u64> {
+    file.query_info(
+        gio::FILE_ATTRIBUTE_TIME_MODIFIED,
+        gio::FileQueryInfoFlags::NONE,
+        gio::Cancellable::NONE,
+    )
+    .ok()
+    .filter(|info| info.has_attribute(gio::FILE_ATTRIBUTE_TIME_MODIFIED))
+    .map(|info| info.attribute_uint64(gio::FILE_ATTRIBUTE_TIME_MODIFIED))
+}
+
+/// Scales the image to SIZE pixels wide, keeping its aspect ratio.
+fn scale_to_width(image: &Pixbuf, size: i32) -> Option<Pixbuf> {
+    let height = (image.height() * size / image.width().max(1)).max(1);
+    image.scale_simple(size, height, InterpType::Bilinear)
+}
+
+/// Looks for a thumbnail that a file manager or ripdrag itself already generated.
+/// The sizes are tried from the one closest to SIZE upwards, then downwards.
+fn lookup(uri: &str, mtime: u64, size: i32) -> Option<Pixbuf> {
+    let (larger, smaller): (Vec<_>, Vec<_>) = CACHE_SIZES
+        .iter()
+        .partition(|(_, cache_size)| *cache_size >= size);
+    let name = cache_name(uri);
+    larger
+        .into_iter()
+        .chain(smaller.into_iter().rev())
+        .find_map(|(dir, _)| {
+            let image = Pixbuf::from_file(cache_dir().join(dir).join(&name)).ok()?;
+            let valid = image.option("tEXt::Thumb::URI").as_deref() == Some(uri)
+                && image.option("tEXt::Thumb::MTime").as_deref() == Some(&*mtime.to_string());
+            valid.then_some(image)
+        })
+}
+
+/// Saves a thumbnail to the cache, so other applications can use it too.
+/// It is written to a temporary file first, so no one reads a half written thumbnail.
+fn store(uri: &str, mtime: u64, dir: &str, image: &Pixbuf) -> Result<(), String> {
+    let dir = cache_dir().join(dir);
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .map_err(|err| err.to_string())?;
+    let path = dir.join(cache_name(uri));
+    let tmp_path = path.with_extension(format!("png.{}", std::process::id()));
+    image
+        .savev(
+            &tmp_path,
+            "png",
+            &[
+                ("tEXt::Thumb::URI", uri),
+                ("tEXt::Thumb::MTime", &mtime.to_string()),
+                ("tEXt::Software", "ripdrag"),
+            ],
+        )
+        .map_err(|err| err.to_string())?;
+    fs::set_permissions(&tmp_path, fs::Permissions::from_mode(0o600))
+        .and_then(|_| fs::rename(&tmp_path, &path))
+        .map_err(|err| {
+            let _ = fs::remove_file(&tmp_path);
+            err.to_string()
+        })
+}
+
+/// Decodes a local image at SIZE pixels, small images are not scaled up.
+fn decode_image(path: &Path, size: i32) -> Option<Pixbuf> {
+    let (width, height) = match Pixbuf::file_info(path) {
+        Some((_, width, height)) => (width.min(size), height.min(size)),
+        None => (size, size),
+    };
+    Pixbuf::from_file_at_scale(path, width, height, true)
+        .map_err(|err| eprintln!("{}", err))
+        .ok()
+}
+
+/// Renders the local image SIZE pixels wide.
+fn render_image(path: &Path, size: i32) -> Option<Pixbuf> {
+    Pixbuf::from_file_at_scale(path, size, -1, true)
+        .map_err(|err| eprintln!("{}", err))
+        .ok()
+}
+
+/// Returns a thumbnail SIZE pixels wide. It comes from the freedesktop thumbnail cache,
+/// so there are previews for every type a file manager can thumbnail. Otherwise local images
+/// are decoded and, with --thumbnailers, other files are passed to the installed thumbnailers.
+/// Local SVGs are always rendered from the file, at exactly SIZE, so they stay sharp.
+/// With --write-thumbnails new thumbnails are saved to the cache. Blocking.
+pub fn load(file: &gio::File, content_type: &str, size: i32) -> Option<Pixbuf> {
+    let args = ARGS.get().unwrap();
+    let path = file.path();
+    if let Some(path) = path
+        .as_ref()
+        .filter(|_| gio::content_type_is_mime_type(content_type, "image/svg+xml"))
+    {
+        return render_image(path, size);
+    }
+
+    let uri = file.uri();
+    let mtime = mtime(file);
+    if let Some(image) = mtime.and_then(|mtime| lookup(&uri, mtime, size)) {
+        return scale_to_width(&image, size);
+    }
+
+    let is_image = gio::content_type_is_mime_type(content_type, "image/*");
+    // without a cache, only the icon sized image is needed
+    if is_image && !args.write_thumbnails {
+        return render_image(&path?, size);
+    }
+
+    let (dir, cache_size) = cache_size(size);
+    let image = match &path {
+        Some(path) if is_image => decode_image(path, cache_size)?,
+        _ if args.thumbnailers => thumbnailer::generate(file, content_type, cache_size)?,
+        _ => return None,
+    };
+    // thumbnails of thumbnails are not cached
+    let cacheable = path.is_none_or(|path| !path.starts_with(cache_dir()));
+    if let Some(mtime) = mtime.filter(|_| args.write_thumbnails && cacheable) {
+        if let Err(err) = store(&uri, mtime, dir, &image) {
+            eprintln!(
+                "Could not save the thumbnail of {}: {}",
+                file.parse_name(),
+                err
+            );
+        }
+    }
+    scale_to_width(&image, size)
+}
@@ -0,0 +1,250 @@
+use std::path::Path;
+
+use clap::ValueEnum;
+use gtk::gdk::DragAction;
+use gtk::gio::{self, File};
+use gtk::glib;
+use gtk::prelude::*;
+
+/// What to do when a dropped file already exists in the destination.
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Keep the existing file and ignore the dropped one
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Add a number to the name of the dropped file
+    #[default]
+    Rename,
+}
+
+/// Actions a drop into a destination directory can perform.
+pub fn dest_actions() -> DragAction {
+    DragAction::COPY | DragAction::MOVE | DragAction::LINK
+}
+
+/// Picks the action to perform out of the ones offered by the source.
+/// When the user did not choose one with a modifier, copy is preferred.
+pub fn pick_action(offered: DragAction) -> DragAction {
+    [DragAction::COPY, DragAction::MOVE, DragAction::LINK]
+        .into_iter()
+        .find(|action| offered.contains(*action))
+        .unwrap_or(DragAction::COPY)
+}
+
+fn is_dir(file: &File) -> bool {
+    file.query_file_type(
+        gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+        gio::Cancellable::NONE,
+    ) == gio::FileType::Directory
+}
+
+/// gio can only copy single files, so directories are walked by hand.
+/// PROGRESS gets the number of bytes copied so far, over all the files.
+pub fn copy_recursive(
+    source: &File,
+    destination: &File,
+    cancellable: Option<&gio::Cancellable>,
+    progress: &mut dyn FnMut(u64),
+) -> Result<(), glib::Error> {
+    copy_tree(source, destination, cancellable, &mut 0, progress)
+}
+
+fn copy_tree(
+    source: &File,
+    destination: &File,
+    cancellable: Option<&gio::Cancellable>,
+    copied: &mut u64,
+    progress: &mut dyn FnMut(u64),
+) -> Result<(), glib::Error> {
+    if source.query_file_type(gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS, cancellable)
+        != gio::FileType::Directory
+    {
+        let start = *copied;
+        source.copy(
+            destination,
+            gio::FileCopyFlags::NOFOLLOW_SYMLINKS,
+            cancellable,
+            Some(&mut |current, _| {
+                *copied = start + current.max(0) as u64;
+                progress(*copied);
+            }),
+        )?;
+        return Ok(());
+    }
+
+    destination.make_directory(cancellable)?;
+    let children = source.enumerate_children(
+        gio::FILE_ATTRIBUTE_STANDARD_NAME,
+        gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+        cancellable,
+    )?;
+    for info in children {
+        let name = info?.name();
+        copy_tree(
+            &source.child(&name),
+            &destination.child(&name),
+            cancellable,
+            copied,
+            progress,
+        )?;
+    }
+    Ok(())
+}
+
+/// Deletes a file or a whole directory tree.
+pub fn delete_recursive(file: &File) -> Result<(), glib::Error> {
+    if is_dir(file) {
+        let children = file.enumerate_children(
+            gio::FILE_ATTRIBUTE_STANDARD_NAME,
+            gio::FileQueryInfoFlags::NOFOLLOW_SYMLINKS,
+            gio::Cancellable::NONE,
+        )?;
+        for info in children {
+            delete_recursive(&file.child(info?.name()))?;
+        }
+    }
+    file.delete(gio::Cancellable::NONE)
+}
+
+/// Returns `name (n).ext` with the first n that is not taken yet.
+fn unique_child(dest_dir: &File, name: &Path, keep_extension: bool) -> File {
+    let (stem, extension) = match (name.file_stem(), name.extension()) {
+        (Some(stem), Some(extension)) if keep_extension => (
+            stem.to_string_lossy(),
+            format!(".{}", extension.to_string_lossy()),
+        ),
+        _ => (name.to_string_lossy(), String::new()),
+    };
+    (1..)
+        .map(|n| dest_dir.child(format!("{} ({}){}", stem, n, extension)))
+        .find(|file| !file.query_exists(gio::Cancellable::NONE))
+        .unwrap()
+}
+
+/// Where a dropped file ends up, and whether it replaces an existing file there.
+struct Destination {
+    target: File,
+    overwrite: bool,
+}
+
+/// Works out where a dropped file ends up. None means that it should be skipped.
+fn destination(file: &File, dest_dir: &File, policy: ConflictPolicy) -> Option<Destination> {
+    let name = file.basename().unwrap_or_else(|| "dropped-file".into());
+    let target = dest_dir.child(&name);
+    if !target.query_exists(gio::Cancellable::NONE) {
+        return Some(Destination {
+            target,
+            overwrite: false,
+        });
+    }
+    match policy {
+        ConflictPolicy::Skip => {
+            eprintln!("{}: File exists, skipping", target.parse_name());
+            None
+        }
+        // overwriting a file with itself would delete it
+        ConflictPolicy::Overwrite if target.equal(file) => None,
+        ConflictPolicy::Overwrite => Some(Destination {
+            target,
+            overwrite: true,
+        }),
+        ConflictPolicy::Rename => Some(Destination {
+            target: unique_child(dest_dir, &name, !is_dir(file)),
+            overwrite: false,
+        }),
+    }
+}
+
+/// Copies, moves or links FILE to TARGET, which must not exist yet.
+/// Returns the action that was performed, which is a copy when a remote file was to be linked.
+fn perform(file: &File, target: &File, action: DragAction) -> Result<DragAction, glib::Error> {
+    match (action, file.path()) {
+        (DragAction::LINK, Some(path)) => {
+            target.make_symbolic_link(&path, gio::Cancellable::NONE)?;
+            Ok(DragAction::LINK)
+        }
+        (DragAction::MOVE, _) => {
+            let result = file.move_(
+                target,
+                gio::FileCopyFlags::NOFOLLOW_SYMLINKS,
+                gio::Cancellable::NONE,
+                None,
+            );
+            // gio can't move directories across file systems
+            if let Err(err) = result {
+                if !err.matches(gio::IOErrorEnum::WouldRecurse) {
+                    return Err(err);
+                }
+                copy_recursive(file, target, None, &mut |_| {})?;
+                // the copy is complete, so what is left of the source is only reported
+                if let Err(err) = delete_recursive(file) {
+                    eprintln!("{}: {}", file.parse_name(), err);
+                }
+            }
+            Ok(DragAction::MOVE)
+        }
+        // remote files can't be linked, so they are copied
+        _ => {
+            copy_recursive(file, target, None, &mut |_| {})?;
+            Ok(DragAction::COPY)
+        }
+    }
+}
+
+/// Replaces TARGET with TMP, a sibling of it.
+fn replace(tmp: &File, target: &File) -> Result<(), glib::Error> {
+    // a rename can't replace a directory, or a file with a directory
+    if is_dir(tmp) || is_dir(target) {
+        delete_recursive(target)?;
+    }
+    tmp.move_(
+        target,
+        gio::FileCopyFlags::OVERWRITE | gio::FileCopyFlags::NOFOLLOW_SYMLINKS,
+        gio::Cancellable::NONE,
+        None,
+    )
+}
+
+/// Returns the created file and the action that was performed.
+/// An existing file is only replaced once the transfer succeeded: the dropped file is
+/// transferred next to it first and then renamed over it.
+fn transfer(
+    file: &File,
+    dest_dir: &File,
+    action: DragAction,
+    policy: ConflictPolicy,
+) -> Result<Option<(File, DragAction)>, glib::Error> {
+    let Some(Destination { target, overwrite }) = destination(file, dest_dir, policy) else {
+        return Ok(None);
+    };
+    if !overwrite {
+        return Ok(Some((target.clone(), perform(file, &target, action)?)));
+    }
+    let name = target.basename().unwrap_or_else(|| "dropped-file".into());
+    let tmp = unique_child(dest_dir, &name, false);
+    // a failed transfer leaves the source in place, so the partial copy can go
+    let performed = perform(file, &tmp, action).inspect_err(|_| {
+        let _ = delete_recursive(&tmp);
+    })?;
+    replace(&tmp, &target)?;
+    Ok(Some((target, performed)))
+}
+/// Copies, moves or links the dropped files into `dest_dir`.
+/// This is blocking and returns the files that were created, with the action performed.
+pub fn transfer_all(
+    files: &[File],
+    dest_dir: &File,
+    action: DragAction,
+    policy: ConflictPolicy,
+) -> Vec<(File, DragAction)> {
+    files
+        .iter()
+        .filter_map(|file| {
+            transfer(file, dest_dir, action, policy)
+                .map_err(|err| eprintln!("{}: {}", file.parse_name(), err))
+                .ok()
+                .flatten()
+        })
+        .collect()
+}
@@ -1,52 +1,118 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
 use gtk::gdk::{ContentProvider, DragAction, FileList};
 use gtk::gio::{self, File, ListStore};
 use gtk::glib::{clone, Bytes};
 use gtk::prelude::*;
-use gtk::{gdk, glib, DragSource, DropTarget, EventSequenceState, Widget};
+use gtk::{gdk, glib, DragSource, DropTargetAsync, EventSequenceState, MultiSelection, Widget};
 
+use crate::dbus::{emit_drag_finished, emit_file_dropped};
+use crate::expand;
 use crate::file_object::FileObject;
+use crate::image_provider::ImageProvider;
+use crate::local_copy::LocalPathsProvider;
+use crate::monitor;
+use crate::output::{print_dragged, print_dropped, DragOutcome};
+use crate::payload::{is_uri_list, save_payload, TEXT_MIME_TYPES};
+use crate::transfer::{copy_recursive, delete_recursive, dest_actions, pick_action, transfer_all};
+use crate::watch;
 use crate::ARGS;
 
 /// Helper record type.
 pub struct ListWidget {
     pub list_model: ListStore,
     pub widget: Widget,
+    /// Only the list view has a selection
+    pub selection: Option<MultiSelection>,
 }
 
 pub fn generate_file_model() -> ListStore {
     let file_model = ListStore::builder()
         .item_type(FileObject::static_type())
         .build();
+    monitor::watch_model(&file_model);
 
-    let files: Vec<FileObject> = ARGS
+    let files: Vec<File> = ARGS
         .get()
         .unwrap()
         .paths
         .iter()
-        .map(|path| FileObject::new(&File::for_path(path)))
+        .map(File::for_commandline_arg)
         .collect();
-    file_model.extend_from_slice(&files);
+    if let Some(dir) = &ARGS.get().unwrap().watch {
+        watch::mirror(&file_model, &File::for_commandline_arg(dir));
+    } else if expand::is_active() {
+        expand::stream_into(&file_model, files);
+    } else {
+        let files: Vec<FileObject> = files.iter().map(FileObject::new).collect();
+        file_model.extend_from_slice(&files);
+    }
     file_model
 }
 
+/// Returns the position of the object for this file.
+pub fn find_file(model: &ListStore, file: &File) -> Option<u32> {
+    model
+        .iter::<FileObject>()
+        .position(|file_object| file_object.is_ok_and(|file_object| file_object.file().equal(file)))
+        .map(|position| position as u32)
+}
+
 /// Returns data for dragging files.
-pub fn generate_content_provider<'a>(
-    paths: impl IntoIterator<Item = &'a String>,
-) -> Option<ContentProvider> {
-    let mut uri_list = paths
-        .into_iter()
+/// Besides the `text/uri-list`, the files are offered as a `FileList` (GTK 4.8), in the format
+/// GNOME apps paste, as plain text paths and, for a single image, as the image itself.
+/// Remote files are offered by URI, they are only downloaded for targets that want paths.
+/// Local files that no longer exist are left out.
+pub fn generate_content_provider(files: &[File]) -> Option<ContentProvider> {
+    let files: Vec<File> = files
+        .iter()
+        .filter(|file| file.path().is_none_or(|path| path.exists()))
         .cloned()
-        .collect::<Vec<String>>()
-        .join("\r\n");
-
-    if uri_list.is_empty() {
+        .collect();
+    let files = files.as_slice();
+    if files.is_empty() {
         return None;
+    }
+    let uris: Vec<String> = files.iter().map(|file| file.uri().to_string()).collect();
+    let paths: Vec<String> = files
+        .iter()
+        .map(|file| file.parse_name().to_string())
+        .collect();
+
+    let uri_list = uris.join("\r\n") + "\r\n";
+    let mut providers = vec![ContentProvider::for_bytes(
+        "text/uri-list",
+        &Bytes::from(uri_list.as_bytes()),
+    )];
+    #[cfg(feature = "v4_8")]
+    providers.push(ContentProvider::for_value(
+        &FileList::from_array(files).to_value(),
+    ));
+    let gnome_files = format!("copy\n{}", uris.join("\n"));
+    providers.push(ContentProvider::for_bytes(
+        "x-special/gnome-copied-files",
+        &Bytes::from(gnome_files.as_bytes()),
+    ));
+    if let [file] = files {
+        if let Some(image) = ImageProvider::new(file) {
+            providers.push(image.upcast());
+        }
+    }
+    if files.iter().all(|file| file.path().is_some()) {
+        let text = Bytes::from(paths.join("\n").as_bytes());
+        providers.push(ContentProvider::for_bytes(
+            "text/plain;charset=utf-8",
+            &text,
+        ));
+        providers.push(ContentProvider::for_bytes("text/plain", &text));
     } else {
-        uri_list += "\r\n";
-        let bytes = Bytes::from(uri_list.as_bytes());
-        Some(ContentProvider::for_bytes("text/uri-list", &bytes))
+        providers.push(LocalPathsProvider::new(files).upcast());
     }
+
+    Some(ContentProvider::new_union(&providers))
 }
+
 /// For the -a or -A flag.
 pub fn setup_drag_source_all(drag_source: &DragSource, list_model: &ListStore) {
     drag_source.connect_prepare(clone!(
@@ -55,125 +121,299 @@ pub fn setup_drag_source_all(drag_source: &DragSource, list_model: &ListStore) {
         #[upgrade_or_default]
         move |me, _, _| {
             me.set_state(EventSequenceState::Claimed);
-            let files: Vec<String> = list_model
+            let files: Vec<File> = list_model
                 .into_iter()
                 .flatten()
-                .map(|file_object| {
-                    file_object
-                        .downcast::<FileObject>()
-                        .unwrap()
-                        .file()
-                        .uri()
-                        .to_string()
-                })
+                .map(|file_object| file_object.downcast::<FileObject>().unwrap().file())
                 .collect();
             generate_content_provider(&files)
         }
     ));
 }
 
-fn create_tmp_file(file: &File) -> Option<FileObject> {
-    let print_err = |err| eprintln!("{}", err);
+/// Returns a file named `name` inside a fresh temporary directory.
+pub fn tmp_location(name: &str) -> Option<File> {
+    let Some(tmp_dir) = glib::mkdtemp(std::env::temp_dir().join("ripdrag-XXXXXX")) else {
+        eprintln!("Could not create a temporary directory");
+        return None;
+    };
+    Some(File::for_path(tmp_dir).child(name))
+}
+
+/// Removes a download that failed or was cancelled from the model and the disk.
+fn discard_download(model: &ListStore, file_object: &FileObject) {
+    if let Some(position) = model.find(file_object) {
+        model.remove(position);
+    }
+    // the temporary directory only holds this file
+    let file = file_object.file();
+    let _ = delete_recursive(&file.parent().unwrap_or(file));
+}
+
+/// Remote files are copied to a temporary location, so they can be dragged out again.
+/// The copy happens in the background: the returned object shows its progress
+/// and can be cancelled through its cancellable.
+async fn create_tmp_file(file: &File, model: &ListStore) -> Option<FileObject> {
     if file.path().is_some() {
-        Some(FileObject::new(file))
-    } else {
-        let info = file.query_info(
-            gio::FILE_ATTRIBUTE_STANDARD_DISPLAY_NAME,
+        return Some(FileObject::new(file));
+    }
+    let info = file
+        .query_info_future(
+            &format!(
+                "{},{},{}",
+                gio::FILE_ATTRIBUTE_STANDARD_DISPLAY_NAME,
+                gio::FILE_ATTRIBUTE_STANDARD_TYPE,
+                gio::FILE_ATTRIBUTE_STANDARD_SIZE
+            ),
             gio::FileQueryInfoFlags::NONE,
-            gio::Cancellable::NONE,
-        );
-        if let Err(err) = info {
-            print_err(err);
-            return None;
+            glib::Priority::DEFAULT,
+        )
+        .await
+        .map_err(|err| eprintln!("{}: {}", file.parse_name(), err))
+        .ok()?;
+
+    let limit = ARGS
+        .get()
+        .unwrap()
+        .download_limit
+        .map(|mib| mib * 1024 * 1024);
+    let exceeds_limit = move |size: i64| limit.is_some_and(|limit| size as u64 > limit);
+    if info.has_attribute(gio::FILE_ATTRIBUTE_STANDARD_SIZE) && exceeds_limit(info.size()) {
+        eprintln!("{}: File exceeds the download limit", file.parse_name());
+        return None;
+    }
+
+    let tmp_file = tmp_location(&info.display_name())?;
+    let cancellable = gio::Cancellable::new();
+    let file_object = FileObject::new(&tmp_file);
+    file_object.set_progress(0.0);
+    file_object.set_cancellable(Some(&cancellable));
+
+    let on_finished = clone!(
+        #[weak]
+        model,
+        #[weak]
+        file_object,
+        move |result: Result<(), glib::Error>| match result {
+            Ok(()) => {
+                file_object.set_progress(1.0);
+                file_object.set_cancellable(None::<gio::Cancellable>);
+                file_object.reload();
+            }
+            Err(err) => {
+                if !err.matches(gio::IOErrorEnum::Cancelled) {
+                    eprintln!("{}", err);
+                }
+                discard_download(&model, &file_object);
+            }
         }
-        let tmp_file = gio::File::new_tmp(None::<String>);
-        match tmp_file {
-            Ok(val) => {
-                let (tmp_file, stream) = val;
-                // download the file
-                let bytes = file.load_bytes(gio::Cancellable::NONE);
-                if bytes.is_err() {
-                    return None;
+    );
+
+    if info.file_type() == gio::FileType::Directory {
+        // directories have no total size, so there is no progress to show
+        let file = file.clone();
+        let cancellable = cancellable.clone();
+        glib::spawn_future_local(async move {
+            let result = gio::spawn_blocking(move || {
+                let display_name = file.parse_name();
+                copy_recursive(&file, &tmp_file, Some(&cancellable), &mut |copied| {
+                    if exceeds_limit(copied as i64) && !cancellable.is_cancelled() {
+                        eprintln!("{}: Directory exceeds the download limit", display_name);
+                        cancellable.cancel();
+                    }
+                })
+            })
+            .await
+            .unwrap_or_else(|_| Err(glib::Error::new(gio::IOErrorEnum::Failed, "Copy panicked")));
+            on_finished(result);
+        });
+    } else {
+        let display_name = file.parse_name();
+        let progress = clone!(
+            #[weak]
+            file_object,
+            #[strong]
+            cancellable,
+            move |current: i64, total: i64| {
+                if exceeds_limit(current) && !cancellable.is_cancelled() {
+                    eprintln!("{}: File exceeds the download limit", display_name);
+                    cancellable.cancel();
                 }
-                // write it
-                let _ = stream
-                    .output_stream()
-                    .write_bytes(&bytes.unwrap().0, gio::Cancellable::NONE)
-                    .map_err(|err| println!("{}", err));
-
-                // rename it
-                // unwrapping basename is safe because the file exists
-                let rename_result = tmp_file.set_display_name(
-                    &format!(
-                        "{}{}",
-                        tmp_file.basename().unwrap().display(),
-                        info.unwrap().display_name()
-                    ),
-                    gio::Cancellable::NONE,
-                );
-
-                if let Err(err) = rename_result {
-                    print_err(err);
-                    Some(FileObject::new(&tmp_file))
-                } else {
-                    Some(FileObject::new(&rename_result.unwrap()))
+                if total > 0 {
+                    file_object.set_progress(current as f64 / total as f64);
                 }
             }
-            Err(err) => {
-                println!("{}", err);
-                None
-            }
+        );
+        file.copy_async(
+            &tmp_file,
+            gio::FileCopyFlags::NONE,
+            glib::Priority::DEFAULT,
+            Some(&cancellable),
+            Some(Box::new(progress)),
+            on_finished,
+        );
+    }
+    Some(file_object)
+}
+
+/// Splits a `text/uri-list` into files, ignoring comments and invalid lines.
+fn parse_uri_list(uri_list: &str) -> Vec<File> {
+    uri_list
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|uri| glib::Uri::parse(uri, glib::UriFlags::PARSE_RELAXED).ok())
+        .map(|uri| File::for_uri(uri.to_str().as_str()))
+        .collect()
+}
+
+/// Reads all the data of one mime type out of a drop.
+async fn read_mime_type(drop: &gdk::Drop, mime_type: &str) -> Result<Bytes, glib::Error> {
+    let (stream, _) = drop
+        .read_future(&[mime_type], glib::Priority::DEFAULT)
+        .await?;
+    let buffer = gio::MemoryOutputStream::new_resizable();
+    buffer
+        .splice_future(
+            &stream,
+            gio::OutputStreamSpliceFlags::CLOSE_SOURCE | gio::OutputStreamSpliceFlags::CLOSE_TARGET,
+            glib::Priority::DEFAULT,
+        )
+        .await?;
+    Ok(buffer.steal_as_bytes())
+}
+
+/// Reads the files out of a drop.
+/// The raw `text/uri-list` is preferred over `FileList`, because the file transfer portal can't
+/// handle directories <https://gitlab.gnome.org/GNOME/gtk/-/issues/5348>.
+/// Text, images and other data are saved to temporary files.
+async fn read_dropped_files(drop: &gdk::Drop) -> Result<Vec<File>, glib::Error> {
+    let formats = drop.formats();
+    let text_mime_type = TEXT_MIME_TYPES
+        .iter()
+        .find(|mime_type| formats.contain_mime_type(mime_type));
+    let mime_types = formats.mime_types();
+    let image_mime_type = mime_types
+        .iter()
+        .find(|mime_type| mime_type.as_str() == "image/png")
+        .or_else(|| {
+            mime_types
+                .iter()
+                .find(|mime_type| mime_type.starts_with("image/"))
+        });
+
+    if formats.contain_mime_type("text/uri-list") {
+        let bytes = read_mime_type(drop, "text/uri-list").await?;
+        Ok(parse_uri_list(&String::from_utf8_lossy(&bytes)))
+    } else if formats
+        .clone()
+        .union_deserialize_types()
+        .contains_type(FileList::static_type())
+    {
+        let value = drop
+            .read_value_future(FileList::static_type(), glib::Priority::DEFAULT)
+            .await?;
+        Ok(value
+            .get::<FileList>()
+            .map(|files| files.files())
+            .unwrap_or_default())
+    } else if let Some(mime_type) = image_mime_type {
+        let bytes = read_mime_type(drop, mime_type).await?;
+        Ok(save_payload(bytes, mime_type).await.into_iter().collect())
+    } else if let Some(mime_type) = text_mime_type {
+        let bytes = read_mime_type(drop, mime_type).await?;
+        let text = String::from_utf8_lossy(&bytes);
+        if is_uri_list(&text) {
+            Ok(parse_uri_list(&text))
+        } else {
+            Ok(save_payload(bytes, mime_type).await.into_iter().collect())
         }
+    } else if let Some(mime_type) = mime_types.iter().find(|mime_type| mime_type.contains('/')) {
+        let bytes = read_mime_type(drop, mime_type).await?;
+        Ok(save_payload(bytes, mime_type).await.into_iter().collect())
+    } else {
+        Ok(vec![])
     }
 }
 
-/// TODO: This will not work for directories <https://gitlab.gnome.org/GNOME/gtk/-/issues/5348>.
-/// Will add dropped files to the model if keep is set.
+/// Will add dropped files and directories to the model if keep or shelf is set.
+/// With --dest they are copied, moved or linked into that directory first.
+/// Any kind of data is accepted, so no formats are set.
 pub fn setup_drop_target(model: &ListStore, widget: &Widget) {
-    let drop_target = DropTarget::builder()
+    let drop_target = DropTargetAsync::builder()
         .name("file-drop-target")
-        .actions(DragAction::COPY)
+        .actions(if ARGS.get().unwrap().dest.is_some() {
+            dest_actions()
+        } else {
+            DragAction::COPY
+        })
         .build();
-    drop_target.set_types(&[FileList::static_type(), glib::types::Type::STRING]);
 
     drop_target.connect_drop(clone!(
         #[weak]
         model,
         #[upgrade_or]
         false,
-        move |_, value, _, _| {
-            let mut files_vec: Vec<File> = vec![];
-
-            if let Ok(file_uris) = value.get::<&str>() {
-                files_vec = file_uris
-                    .split('\n')
-                    .collect::<Vec<&str>>()
-                    .iter()
-                    .filter_map(|uri| glib::Uri::parse(uri, glib::UriFlags::PARSE_RELAXED).ok())
-                    .map(|uri| File::for_uri(uri.to_str().as_str()))
-                    .collect();
-            } else if let Ok(files) = value.get::<gdk::FileList>() {
-                files_vec = files.files();
-            }
+        move |_, drop, _, _| {
+            let drop = drop.clone();
+            glib::spawn_future_local(clone!(
+                #[weak]
+                model,
+                async move {
+                    let mut files_vec = match read_dropped_files(&drop).await {
+                        Ok(files) => files,
+                        Err(err) => {
+                            eprintln!("{}", err);
+                            vec![]
+                        }
+                    };
 
-            if files_vec.is_empty() {
-                return false;
-            }
+                    let mut action = DragAction::COPY;
+                    let mut dropped: Vec<(File, DragAction)> = files_vec
+                        .iter()
+                        .map(|file| (file.clone(), action))
+                        .collect();
+                    if let Some(dest) = &ARGS.get().unwrap().dest {
+                        action = pick_action(drop.actions());
+                        let dest = File::for_path(dest);
+                        let policy = ARGS.get().unwrap().on_conflict;
+                        // remote files that were to be linked are copied
+                        dropped = gio::spawn_blocking(move || {
+                            transfer_all(&files_vec, &dest, action, policy)
+                        })
+                        .await
+                        .unwrap_or_default();
+                        files_vec = dropped.iter().map(|(file, _)| file.clone()).collect();
+                    }
 
-            for item in &files_vec {
-                println!("{}", item.parse_name());
-            }
+                    if files_vec.is_empty() {
+                        drop.finish(DragAction::empty());
+                        return;
+                    }
 
-            if ARGS.get().unwrap().keep {
-                let file_objs: Vec<FileObject> = files_vec
-                    .iter()
-                    .filter_map(|item| create_tmp_file(item))
-                    .collect();
-                model.extend_from_slice(&file_objs);
-            } else if ARGS.get().unwrap().and_exit {
-                std::process::exit(0);
-            }
+                    print_dropped(&dropped, action).await;
+                    emit_file_dropped(&files_vec);
 
+                    let args = ARGS.get().unwrap();
+                    if args.keep || args.shelf {
+                        drop.finish(action);
+                        for file in &files_vec {
+                            // the shelf may get back its own files
+                            if args.shelf && find_file(&model, file).is_some() {
+                                continue;
+                            }
+                            if let Some(file_object) = create_tmp_file(file, &model).await {
+                                model.append(&file_object);
+                            }
+                        }
+                    } else {
+                        drop.finish(action);
+                        if args.and_exit {
+                            std::process::exit(0);
+                        }
+                    }
+                }
+            ));
             true
         }
     ));
@@ -181,8 +421,80 @@ pub fn setup_drop_target(model: &ListStore, widget: &Widget) {
     widget.add_controller(drop_target);
 }
 
-pub fn drag_source_and_exit(drag_source: &DragSource) {
-    drag_source.connect_drag_end(|_, _, _| {
-        std::process::exit(0);
-    });
+/// Reads the dragged files back out of the content of a drag.
+async fn dragged_files(drag: &gdk::Drag) -> Vec<File> {
+    let buffer = gio::MemoryOutputStream::new_resizable();
+    let result = drag
+        .content()
+        .write_mime_type_future("text/uri-list", &buffer, glib::Priority::DEFAULT)
+        .await
+        .and_then(|_| buffer.close(gio::Cancellable::NONE));
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        return vec![];
+    }
+    parse_uri_list(&String::from_utf8_lossy(&buffer.steal_as_bytes()))
+}
+
+/// Removes moved files from the model, and with --trash, trashes the ones that still exist
+/// because the target only copied them.
+async fn remove_moved(model: &ListStore, files: &[File]) {
+    for file in files {
+        if let Some(position) = find_file(model, file) {
+            model.remove(position);
+        }
+        if ARGS.get().unwrap().trash && file.query_exists(gio::Cancellable::NONE) {
+            if let Err(err) = file.trash_future(glib::Priority::DEFAULT).await {
+                eprintln!("{}: {}", file.parse_name(), err);
+            }
+        }
+    }
+}
+
+/// Sets up what happens when a drag from this source ends.
+/// Prints how it ended and, with --move, removes moved files from the model.
+/// With --and-exit, exits afterwards with a nonzero status if the drag was cancelled or failed.
+pub fn setup_drag_end(drag_source: &DragSource, model: &ListStore) {
+    if ARGS.get().unwrap().move_files {
+        drag_source.set_actions(DragAction::COPY | DragAction::MOVE);
+    }
+
+    // drag-cancel is emitted right before drag-end
+    let cancel_reason = Rc::new(Cell::new(None));
+    drag_source.connect_drag_begin(clone!(
+        #[strong]
+        cancel_reason,
+        move |_, _| cancel_reason.set(None)
+    ));
+    drag_source.connect_drag_cancel(clone!(
+        #[strong]
+        cancel_reason,
+        move |_, _, reason| {
+            cancel_reason.set(Some(reason));
+            false
+        }
+    ));
+    drag_source.connect_drag_end(clone!(
+        #[weak]
+        model,
+        move |_, drag, _| {
+            let outcome = match cancel_reason.get() {
+                None => DragOutcome::Dropped(drag.selected_action()),
+                Some(gdk::DragCancelReason::Error) => DragOutcome::Failed,
+                Some(_) => DragOutcome::Cancelled,
+            };
+            let drag = drag.clone();
+            glib::spawn_future_local(async move {
+                let files = dragged_files(&drag).await;
+                print_dragged(&files, outcome).await;
+                emit_drag_finished(&files, outcome);
+                if outcome == DragOutcome::Dropped(DragAction::MOVE) {
+                    remove_moved(&model, &files).await;
+                }
+                if ARGS.get().unwrap().and_exit {
+                    std::process::exit(outcome.exit_code());
+                }
+            });
+        }
+    ));
 }
@@ -0,0 +1,164 @@
+use std::cell::Cell;
+use std::cmp::Reverse;
+use std::rc::Rc;
+
+use gtk::gio::{self, File, FileMonitorEvent, ListStore};
+use gtk::glib::{self, clone};
+use gtk::prelude::*;
+
+use crate::expand::is_included;
+use crate::file_object::FileObject;
+use crate::util::find_file;
+use crate::ARGS;
+
+/// Whether a file of the watched directory belongs in the list.
+fn is_shown(file: &File, info: &gio::FileInfo) -> bool {
+    !info.is_hidden() && file.parent().is_some_and(|dir| is_included(file, &dir))
+}
+
+/// With --newest, removes the oldest files at the end of the list.
+fn keep_newest(model: &ListStore) {
+    if let Some(newest) = ARGS.get().unwrap().newest {
+        while model.n_items() > newest {
+            model.remove(model.n_items() - 1);
+        }
+    }
+}
+
+/// Adds a file that appeared in the directory at the top, as it is the newest one.
+/// With --newest the oldest files make room for it.
+fn add_new_file(model: &ListStore, file: &File) {
+    if find_file(model, file).is_some() {
+        return;
+    }
+    let Ok(info) = file.query_info(
+        gio::FILE_ATTRIBUTE_STANDARD_IS_HIDDEN,
+        gio::FileQueryInfoFlags::NONE,
+        gio::Cancellable::NONE,
+    ) else {
+        return;
+    };
+    if !is_shown(file, &info) {
+        return;
+    }
+    model.insert(0, &FileObject::new(file));
+    keep_newest(model);
+}
+
+/// Lists the files of the directory, newest first.
+async fn read_dir(dir: &File) -> Result<Vec<File>, glib::Error> {
+    let enumerator = dir
+        .enumerate_children_future(
+            &format!(
+                "{},{},{}",
+                gio::FILE_ATTRIBUTE_STANDARD_NAME,
+                gio::FILE_ATTRIBUTE_STANDARD_IS_HIDDEN,
+                gio::FILE_ATTRIBUTE_TIME_MODIFIED
+            ),
+            gio::FileQueryInfoFlags::NONE,
+            glib::Priority::DEFAULT,
+        )
+        .await?;
+    let mut files = Vec::new();
+    loop {
+        let infos = enumerator
+            .next_files_future(256, glib::Priority::DEFAULT)
+            .await?;
+        if infos.is_empty() {
+            break;
+        }
+        files.extend(infos.into_iter().filter_map(|info| {
+            let file = dir.child(info.name());
+            is_shown(&file, &info).then(|| {
+                let modified = info.attribute_uint64(gio::FILE_ATTRIBUTE_TIME_MODIFIED);
+                (modified, file)
+            })
+        }));
+    }
+    files.sort_by_key(|(modified, _)| Reverse(*modified));
+    if let Some(newest) = ARGS.get().unwrap().newest {
+        files.truncate(newest as usize);
+    }
+    Ok(files.into_iter().map(|(_, file)| file).collect())
+}
+
+/// Adds the files of the directory that are not listed yet.
+async fn add_missing(model: &ListStore, dir: &File) {
+    match read_dir(dir).await {
+        Ok(files) => {
+            // files that were created in the meantime are already there
+            let files: Vec<FileObject> = files
+                .iter()
+                .filter(|file| find_file(model, file).is_none())
+                .map(FileObject::new)
+                .collect();
+            model.extend_from_slice(&files);
+            keep_newest(model);
+        }
+        Err(err) => eprintln!("{}: {}", dir.parse_name(), err),
+    }
+}
+
+/// Makes the model mirror the files of the directory, newest first.
+/// Deleted and renamed files are handled by the monitors of the files themselves.
+pub fn mirror(model: &ListStore, dir: &File) {
+    let monitor =
+        match dir.monitor_directory(gio::FileMonitorFlags::WATCH_MOVES, gio::Cancellable::NONE) {
+            Ok(monitor) => monitor,
+            Err(err) => {
+                eprintln!("{}: {}", dir.parse_name(), err);
+                return;
+            }
+        };
+    monitor.connect_changed(clone!(
+        #[weak]
+        model,
+        move |_, file, other_file, event| match event {
+            FileMonitorEvent::Created | FileMonitorEvent::MovedIn => add_new_file(&model, file),
+            // a file that was renamed while in the list is followed by its own monitor
+            FileMonitorEvent::Renamed if find_file(&model, file).is_none() => {
+                if let Some(other_file) = other_file {
+                    add_new_file(&model, other_file);
+                }
+            }
+            _ => {}
+        }
+    ));
+
+    // with --newest, the next newest files take the place of removed ones
+    let refilling = Rc::new(Cell::new(false));
+    model.connect_items_changed(clone!(
+        #[strong]
+        dir,
+        move |model, _, removed, _| {
+            // the directory is watched as long as the model exists
+            let _ = &monitor;
+            let Some(newest) = ARGS.get().unwrap().newest else {
+                return;
+            };
+            if removed == 0 || model.n_items() >= newest || refilling.replace(true) {
+                return;
+            }
+            glib::spawn_future_local(clone!(
+                #[weak]
+                model,
+                #[strong]
+                dir,
+                #[strong]
+                refilling,
+                async move {
+                    add_missing(&model, &dir).await;
+                    refilling.set(false);
+                }
+            ));
+        }
+    ));
+
+    glib::spawn_future_local(clone!(
+        #[weak]
+        model,
+        #[strong]
+        dir,
+        async move { add_missing(&model, &dir).await }
+    ));
+}